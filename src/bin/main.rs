@@ -1,6 +1,9 @@
-use aoc::runner;
+use aoc::{day10, runner};
 
 use std::env;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::process;
 
 fn main() {
     let args: Vec<_> = env::args().collect();
@@ -9,9 +12,42 @@ fn main() {
         panic!("Error: must provide at least one day");
     }
 
+    if args[1] == "lint" {
+        lint(&args[2..]);
+    }
+
     let days: Vec<u32> = args[1..]
         .iter()
         .map(|arg| arg.parse().expect("Must provide integers"))
         .collect();
     days.iter().for_each(|&day| runner::run(day));
 }
+
+/// Lints navigation subsystem files (or stdin, given "-"), exiting with 1 if
+/// any line is corrupted, or 2 if a file couldn't be read.
+fn lint(paths: &[String]) -> ! {
+    if paths.is_empty() {
+        eprintln!("usage: aoc lint <file>...");
+        process::exit(2);
+    }
+
+    let mut corrupted = false;
+    for path in paths {
+        let stdout = io::stdout();
+        let result = if path == "-" {
+            day10::lint("<stdin>", io::stdin().lock(), stdout.lock())
+        } else {
+            File::open(path).and_then(|file| day10::lint(path, BufReader::new(file), stdout.lock()))
+        };
+
+        match result {
+            Ok(summary) => corrupted |= summary.corrupted > 0,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                process::exit(2);
+            }
+        }
+    }
+
+    process::exit(if corrupted { 1 } else { 0 });
+}
@@ -1,3 +1,8 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::BufRead;
+
 pub fn run() {
     let input = puzzle_input();
     println!("day01.part1.solution = {}", solve_part1(input));
@@ -7,14 +12,148 @@ pub fn run() {
 fn solve_part1(input: &str) -> usize {
     let depths = parse_input(input);
 
-    depths.windows(2).filter(|&pair| pair[1] > pair[0]).count()
+    analyze(&depths, 1).increases
 }
 
 fn solve_part2(input: &str) -> usize {
     let depths = parse_input(input);
 
-    let sums: Vec<u64> = depths.windows(3).map(|w| w.iter().sum()).collect();
-    sums.windows(2).filter(|&pair| pair[1] > pair[0]).count()
+    analyze(&depths, 3).increases
+}
+
+/// Summarizes how the sums of consecutive sliding windows over a depth log
+/// change from one window to the next.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SweepReport {
+    pub increases: usize,
+    pub decreases: usize,
+    pub plateaus: usize,
+    /// The largest number of consecutive increases between windows.
+    pub longest_increasing_run: usize,
+}
+
+impl SweepReport {
+    /// Records one comparison between a window sum and the one before it.
+    /// `run` tracks the length of the increasing run in progress.
+    fn record(&mut self, run: &mut usize, ordering: Ordering) {
+        match ordering {
+            Ordering::Greater => {
+                self.increases += 1;
+                *run += 1;
+                self.longest_increasing_run = self.longest_increasing_run.max(*run);
+            }
+            Ordering::Less => {
+                self.decreases += 1;
+                *run = 0;
+            }
+            Ordering::Equal => {
+                self.plateaus += 1;
+                *run = 0;
+            }
+        }
+    }
+}
+
+/// Compares the sums of each pair of adjacent windows of size `window` in a
+/// single pass over `depths`.
+///
+/// Adjacent windows share all but one depth, so the comparison of their sums
+/// is the same as comparing the depth entering the window with the one
+/// leaving it. That means we never need to compute the sums themselves.
+///
+/// ```
+/// use aoc::day01::analyze;
+/// let report = analyze(&[199, 200, 208, 210, 200, 207, 240, 269, 260, 263], 3);
+/// assert_eq!(report.increases, 5);
+/// assert_eq!(report.plateaus, 1);
+/// ```
+pub fn analyze(depths: &[u64], window: usize) -> SweepReport {
+    assert!(window > 0, "window size must be positive");
+
+    let mut report = SweepReport::default();
+    let mut run = 0;
+
+    for (leaving, entering) in depths.iter().zip(depths.iter().skip(window)) {
+        report.record(&mut run, entering.cmp(leaving));
+    }
+
+    report
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    Io(std::io::Error),
+    InvalidDepth { line: usize, text: String },
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadError::Io(e) => write!(f, "failed to read depths: {}", e),
+            ReadError::InvalidDepth { line, text } => {
+                write!(f, "line {}: invalid depth {:?}", line, text)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// Streaming version of [`analyze`] which reads one depth per line from
+/// `reader` and produces a report for each of the requested window sizes.
+///
+/// Only the last `max(windows)` depths are kept around, so memory use does
+/// not grow with the length of the log. Blank lines are skipped; anything
+/// else which isn't a depth is reported along with its (1-based) line number.
+///
+/// ```
+/// use aoc::day01::analyze_reader;
+/// use std::io::Cursor;
+/// let log = Cursor::new("199\n200\n208\n210\n200\n207\n240\n269\n260\n263\n");
+/// let reports = analyze_reader(log, &[1, 3]).unwrap();
+/// assert_eq!(reports[0].increases, 7);
+/// assert_eq!(reports[1].increases, 5);
+/// ```
+pub fn analyze_reader<R: BufRead>(
+    reader: R,
+    windows: &[usize],
+) -> Result<Vec<SweepReport>, ReadError> {
+    assert!(
+        windows.iter().all(|&w| w > 0),
+        "window size must be positive"
+    );
+
+    let max_window = windows.iter().copied().max().unwrap_or(0);
+    let mut recent: VecDeque<u64> = VecDeque::with_capacity(max_window + 1);
+    let mut reports = vec![SweepReport::default(); windows.len()];
+    let mut runs = vec![0; windows.len()];
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line.map_err(ReadError::Io)?;
+        let text = line.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let depth = text.parse::<u64>().map_err(|_| ReadError::InvalidDepth {
+            line: idx + 1,
+            text: text.to_string(),
+        })?;
+
+        for ((&window, report), run) in windows.iter().zip(&mut reports).zip(&mut runs) {
+            if recent.len() >= window {
+                let leaving = recent[recent.len() - window];
+                report.record(run, depth.cmp(&leaving));
+            }
+        }
+
+        recent.push_back(depth);
+        if recent.len() > max_window {
+            recent.pop_front();
+        }
+    }
+
+    Ok(reports)
 }
 
 fn parse_input(input: &str) -> Vec<u64> {
@@ -42,4 +181,52 @@ mod test {
     fn verify_example_input_part2() {
         assert_eq!(5, solve_part2(example_input()));
     }
+
+    #[test]
+    fn analyzes_windows_of_any_size() {
+        let depths = parse_input(example_input());
+
+        assert_eq!(
+            SweepReport {
+                increases: 7,
+                decreases: 2,
+                plateaus: 0,
+                longest_increasing_run: 3,
+            },
+            analyze(&depths, 1)
+        );
+        assert_eq!(
+            SweepReport {
+                increases: 5,
+                decreases: 1,
+                plateaus: 1,
+                longest_increasing_run: 4,
+            },
+            analyze(&depths, 3)
+        );
+        assert_eq!(SweepReport::default(), analyze(&depths, 10));
+    }
+
+    #[test]
+    fn streaming_matches_in_memory_analysis() {
+        let depths = parse_input(puzzle_input());
+        let windows = [1, 2, 3, 7];
+        let reports = analyze_reader(puzzle_input().as_bytes(), &windows).unwrap();
+
+        for (&window, report) in windows.iter().zip(reports) {
+            assert_eq!(analyze(&depths, window), report);
+        }
+    }
+
+    #[test]
+    fn streaming_reports_bad_lines() {
+        let log = "199\n200\n\n20x\n210\n";
+        match analyze_reader(log.as_bytes(), &[1]) {
+            Err(ReadError::InvalidDepth { line, text }) => {
+                assert_eq!(4, line);
+                assert_eq!("20x", text);
+            }
+            other => panic!("expected an invalid depth, got {:?}", other),
+        }
+    }
 }
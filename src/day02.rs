@@ -1,3 +1,6 @@
+use std::fmt;
+use std::iter::Peekable;
+
 pub fn run() {
     let input = puzzle_input();
     println!("day02.part1.solution = {}", solve_part1(input));
@@ -7,36 +10,152 @@ pub fn run() {
 fn solve_part1(input: &str) -> i32 {
     let commands = parse_input(input);
 
-    let final_pos = commands.iter().fold((0, 0), |(x, y), cmd| match cmd {
-        Command::Forward(n) => (x + n, y),
-        Command::Down(n) => (x, y + n),
-        Command::Up(n) => (x, y - n),
-    });
+    let mut sub = PlainSubmarine::default();
+    sub.follow(&commands);
 
-    final_pos.0 * final_pos.1
+    sub.position().product()
 }
 
 fn solve_part2(input: &str) -> i32 {
     let commands = parse_input(input);
 
-    let final_pos = commands
-        .iter()
-        .fold((0, 0, 0), |(x, y, aim), cmd| match cmd {
-            Command::Forward(n) => (x + n, y + aim * n, aim),
-            Command::Down(n) => (x, y, aim + n),
-            Command::Up(n) => (x, y, aim - n),
-        });
+    let mut sub = AimingSubmarine::default();
+    sub.follow(&commands);
+
+    sub.position().product()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Position {
+    pub horizontal: i32,
+    pub depth: i32,
+}
+
+impl Position {
+    pub fn product(&self) -> i32 {
+        self.horizontal * self.depth
+    }
+}
+
+/// A movement model: something which knows how to interpret each command
+/// and where it ended up as a result. New rule sets can be modeled by
+/// implementing this trait.
+pub trait Submarine {
+    fn execute(&mut self, cmd: &Command);
+
+    fn position(&self) -> Position;
+
+    fn follow(&mut self, commands: &[Command]) {
+        for cmd in commands {
+            self.execute(cmd);
+        }
+    }
+}
+
+/// The model from part 1, where up and down change the depth directly.
+#[derive(Clone, Debug, Default)]
+pub struct PlainSubmarine {
+    position: Position,
+}
+
+impl Submarine for PlainSubmarine {
+    fn execute(&mut self, cmd: &Command) {
+        match cmd {
+            Command::Forward(n) => self.position.horizontal += n,
+            Command::Backward(n) => self.position.horizontal -= n,
+            Command::Down(n) => self.position.depth += n,
+            Command::Up(n) => self.position.depth -= n,
+        }
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+}
+
+/// The model from part 2, where up and down change the aim, and the depth
+/// only changes when moving forward (or backward, which retraces the aim).
+#[derive(Clone, Debug, Default)]
+pub struct AimingSubmarine {
+    position: Position,
+    aim: i32,
+}
+
+impl Submarine for AimingSubmarine {
+    fn execute(&mut self, cmd: &Command) {
+        match cmd {
+            Command::Forward(n) => {
+                self.position.horizontal += n;
+                self.position.depth += self.aim * n;
+            }
+            Command::Backward(n) => {
+                self.position.horizontal -= n;
+                self.position.depth -= self.aim * n;
+            }
+            Command::Down(n) => self.aim += n,
+            Command::Up(n) => self.aim -= n,
+        }
+    }
+
+    fn position(&self) -> Position {
+        self.position
+    }
+}
+
+/// Wraps any other submarine and records its position after every command,
+/// starting with where it was before the first one.
+///
+/// ```
+/// use aoc::day02::{Command, PlainSubmarine, Submarine, Trace};
+/// let mut sub = Trace::new(PlainSubmarine::default());
+/// sub.follow(&[Command::Forward(5), Command::Down(2)]);
+/// assert_eq!(sub.to_csv(), "step,horizontal,depth\n0,0,0\n1,5,0\n2,5,2\n");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Trace<S> {
+    sub: S,
+    positions: Vec<Position>,
+}
+
+impl<S: Submarine> Trace<S> {
+    pub fn new(sub: S) -> Self {
+        let positions = vec![sub.position()];
+        Trace { sub, positions }
+    }
+
+    pub fn positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    /// Exports the trace as CSV with one row per step.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("step,horizontal,depth\n");
+        for (step, pos) in self.positions.iter().enumerate() {
+            csv.push_str(&format!("{},{},{}\n", step, pos.horizontal, pos.depth));
+        }
+        csv
+    }
+}
+
+impl<S: Submarine> Submarine for Trace<S> {
+    fn execute(&mut self, cmd: &Command) {
+        self.sub.execute(cmd);
+        self.positions.push(self.sub.position());
+    }
 
-    final_pos.0 * final_pos.1
+    fn position(&self) -> Position {
+        self.sub.position()
+    }
 }
 
 fn puzzle_input() -> &'static str {
     include_str!("../inputs/day02.txt")
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Command {
     Forward(i32),
+    Backward(i32),
     Down(i32),
     Up(i32),
 }
@@ -48,27 +167,221 @@ impl Command {
     /// ```
     /// use aoc::day02::Command;
     /// assert_eq!(Command::parse("forward 3"), Some(Command::Forward(3)));
+    /// assert_eq!(Command::parse("backward 2"), Some(Command::Backward(2)));
     /// assert_eq!(Command::parse("blah blah"), None);
     /// assert_eq!(Command::parse("forward three"), None);
     /// ```
     pub fn parse(cmd: &str) -> Option<Command> {
         match cmd.split_once(" ") {
-            Some((dir, amt)) => {
-                let amt = amt.parse::<i32>();
-                match (dir, amt) {
-                    ("forward", Ok(x)) => Some(Command::Forward(x)),
-                    ("down", Ok(x)) => Some(Command::Down(x)),
-                    ("up", Ok(x)) => Some(Command::Up(x)),
-                    _ => None,
+            Some((dir, amt)) => Command::from_parts(dir, amt.parse::<i32>().ok()?),
+            _ => None,
+        }
+    }
+
+    fn from_parts(dir: &str, amt: i32) -> Option<Command> {
+        match dir {
+            "forward" => Some(Command::Forward(amt)),
+            "backward" => Some(Command::Backward(amt)),
+            "down" => Some(Command::Down(amt)),
+            "up" => Some(Command::Up(amt)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ScriptError {
+    UnknownCommand { line: usize, command: String },
+    MissingAmount { line: usize },
+    InvalidAmount { line: usize, amount: String },
+    Unexpected { line: usize, token: String },
+    UnclosedRepeat { line: usize },
+    TooLong { line: usize },
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::UnknownCommand { line, command } => {
+                write!(f, "line {}: unknown command {:?}", line, command)
+            }
+            ScriptError::MissingAmount { line } => write!(f, "line {}: missing amount", line),
+            ScriptError::InvalidAmount { line, amount } => {
+                write!(f, "line {}: invalid amount {:?}", line, amount)
+            }
+            ScriptError::Unexpected { line, token } => {
+                write!(f, "line {}: unexpected {:?}", line, token)
+            }
+            ScriptError::UnclosedRepeat { line } => {
+                write!(f, "line {}: repeat block is never closed", line)
+            }
+            ScriptError::TooLong { line } => write!(
+                f,
+                "line {}: repeat block expands to more than {} commands",
+                line, MAX_COMMANDS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// The most commands a block may expand to, so a few nested `repeat`s
+/// can't ask for more memory than there is.
+pub const MAX_COMMANDS: usize = 1 << 20;
+
+/// Parses a command script into the flat list of commands it describes.
+///
+/// On top of the plain puzzle format, scripts may contain `#` comments and
+/// `repeat N { ... }` blocks, which can be nested and span several lines.
+/// Each command and its amount must be on the same line. Anything that
+/// isn't understood is reported with its line number rather than skipped,
+/// as is a `repeat` which would make a block longer than `MAX_COMMANDS`.
+///
+/// ```
+/// use aoc::day02::{parse_script, Command};
+/// let script = "
+///     repeat 2 { forward 1 down 3 }  # dive twice
+///     backward 2                     # and back up a bit
+/// ";
+/// assert_eq!(
+///     parse_script(script).unwrap(),
+///     vec![
+///         Command::Forward(1),
+///         Command::Down(3),
+///         Command::Forward(1),
+///         Command::Down(3),
+///         Command::Backward(2),
+///     ]
+/// );
+/// ```
+pub fn parse_script(script: &str) -> Result<Vec<Command>, ScriptError> {
+    let mut tokens = tokenize(script).into_iter().peekable();
+    parse_block(&mut tokens, None)
+}
+
+struct Token<'a> {
+    line: usize,
+    text: &'a str,
+}
+
+fn tokenize(script: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+
+    for (idx, line) in script.lines().enumerate() {
+        let code = match line.split_once('#') {
+            Some((code, _comment)) => code,
+            None => line,
+        };
+
+        for word in code.split_whitespace() {
+            // Braces don't need to be separated from their neighbors, so
+            // split them out into tokens of their own.
+            let mut rest = word;
+            while let Some(pos) = rest.find(['{', '}']) {
+                if pos > 0 {
+                    tokens.push(Token {
+                        line: idx + 1,
+                        text: &rest[..pos],
+                    });
                 }
+                tokens.push(Token {
+                    line: idx + 1,
+                    text: &rest[pos..pos + 1],
+                });
+                rest = &rest[pos + 1..];
+            }
+            if !rest.is_empty() {
+                tokens.push(Token {
+                    line: idx + 1,
+                    text: rest,
+                });
             }
-            _ => None,
         }
     }
+
+    tokens
+}
+
+/// Parses commands until the end of the block opened on line `opened`, or
+/// until the end of the script for the top level.
+fn parse_block<'a, I>(
+    tokens: &mut Peekable<I>,
+    opened: Option<usize>,
+) -> Result<Vec<Command>, ScriptError>
+where
+    I: Iterator<Item = Token<'a>>,
+{
+    let mut commands = vec![];
+
+    while let Some(token) = tokens.next() {
+        match token.text {
+            "}" if opened.is_some() => return Ok(commands),
+            "repeat" => {
+                let count = parse_amount::<usize, _>(tokens, token.line)?;
+                match tokens.next() {
+                    Some(Token { text: "{", .. }) => {}
+                    Some(other) => {
+                        return Err(ScriptError::Unexpected {
+                            line: other.line,
+                            token: other.text.to_string(),
+                        })
+                    }
+                    None => return Err(ScriptError::UnclosedRepeat { line: token.line }),
+                }
+
+                let body = parse_block(tokens, Some(token.line))?;
+                let expanded = body.len().saturating_mul(count);
+                if commands.len().saturating_add(expanded) > MAX_COMMANDS {
+                    return Err(ScriptError::TooLong { line: token.line });
+                }
+                commands.extend(body.iter().cycle().take(expanded));
+            }
+            "{" | "}" => {
+                return Err(ScriptError::Unexpected {
+                    line: token.line,
+                    token: token.text.to_string(),
+                })
+            }
+            dir => {
+                if Command::from_parts(dir, 0).is_none() {
+                    return Err(ScriptError::UnknownCommand {
+                        line: token.line,
+                        command: dir.to_string(),
+                    });
+                }
+                let amt = parse_amount::<i32, _>(tokens, token.line)?;
+                commands.push(Command::from_parts(dir, amt).unwrap());
+            }
+        }
+    }
+
+    match opened {
+        Some(line) => Err(ScriptError::UnclosedRepeat { line }),
+        None => Ok(commands),
+    }
+}
+
+/// Parses the amount following a command, which has to be on the same line.
+fn parse_amount<'a, T, I>(tokens: &mut Peekable<I>, line: usize) -> Result<T, ScriptError>
+where
+    T: std::str::FromStr,
+    I: Iterator<Item = Token<'a>>,
+{
+    match tokens.next_if(|t| t.line == line) {
+        Some(token) => token
+            .text
+            .parse::<T>()
+            .map_err(|_| ScriptError::InvalidAmount {
+                line,
+                amount: token.text.to_string(),
+            }),
+        None => Err(ScriptError::MissingAmount { line }),
+    }
 }
 
 fn parse_input(input: &str) -> Vec<Command> {
-    input.lines().filter_map(Command::parse).collect()
+    parse_script(input).unwrap()
 }
 
 #[cfg(test)]
@@ -88,4 +401,96 @@ mod test {
     fn verify_example_input_part2() {
         assert_eq!(900, solve_part2(example_input()));
     }
+
+    #[test]
+    fn traces_positions() {
+        let commands = parse_input(example_input());
+        let mut sub = Trace::new(AimingSubmarine::default());
+        sub.follow(&commands);
+
+        let positions = sub.positions();
+        assert_eq!(commands.len() + 1, positions.len());
+        assert_eq!(Position::default(), positions[0]);
+        assert_eq!(
+            Position {
+                horizontal: 13,
+                depth: 40
+            },
+            positions[4]
+        );
+        assert_eq!(900, sub.position().product());
+    }
+
+    #[test]
+    fn parses_scripts() {
+        let script = "
+            forward 5 # full speed ahead
+            repeat 2 {
+                down 1
+                repeat 3 { up 2 }
+            }
+            backward 1
+        ";
+        let commands = parse_script(script).unwrap();
+
+        assert_eq!(10, commands.len());
+        assert_eq!(Command::Forward(5), commands[0]);
+        assert_eq!(Command::Down(1), commands[5]);
+        assert_eq!(Command::Up(2), commands[8]);
+        assert_eq!(Command::Backward(1), commands[9]);
+    }
+
+    #[test]
+    fn reports_script_errors() {
+        assert_eq!(
+            Err(ScriptError::UnknownCommand {
+                line: 2,
+                command: "sideways".to_string()
+            }),
+            parse_script("forward 1\nsideways 3")
+        );
+        assert_eq!(
+            Err(ScriptError::InvalidAmount {
+                line: 1,
+                amount: "three".to_string()
+            }),
+            parse_script("forward three")
+        );
+        assert_eq!(
+            Err(ScriptError::MissingAmount { line: 1 }),
+            parse_script("down\n3")
+        );
+        assert_eq!(
+            Err(ScriptError::UnclosedRepeat { line: 1 }),
+            parse_script("repeat 2 {\nup 1")
+        );
+        assert_eq!(
+            Err(ScriptError::Unexpected {
+                line: 1,
+                token: "}".to_string()
+            }),
+            parse_script("up 1 }")
+        );
+    }
+
+    #[test]
+    fn limits_repeat_expansion() {
+        assert_eq!(
+            Err(ScriptError::TooLong { line: 1 }),
+            parse_script("repeat 1000 {\nrepeat 1000 { repeat 1000 { up 1 } }\n}")
+        );
+        assert_eq!(
+            Err(ScriptError::TooLong { line: 2 }),
+            parse_script("up 1\nrepeat 18446744073709551615 { down 1 }")
+        );
+
+        assert_eq!(Ok(vec![]), parse_script("repeat 18446744073709551615 { }"));
+
+        let commands = parse_script(&format!("repeat {} {{ up 1 }}", MAX_COMMANDS)).unwrap();
+        assert_eq!(MAX_COMMANDS, commands.len());
+        assert_eq!(
+            Err(ScriptError::TooLong { line: 2 }),
+            parse_script(&format!("up 1\nrepeat {} {{ up 1 }}", MAX_COMMANDS))
+        );
+    }
 }
@@ -1,4 +1,5 @@
-use num::PrimInt;
+use num::{BigUint, PrimInt};
+use std::cmp::Ordering;
 
 pub fn run() {
     let input = puzzle_input();
@@ -6,62 +7,335 @@ pub fn run() {
     println!("day03.part2.solution = {}", solve_part2(input));
 }
 
-fn solve_part1(input: &str) -> u32 {
-    let bit_width = input.lines().next().unwrap().len();
+fn solve_part1(input: &str) -> BigUint {
+    let report = Report::parse(input);
 
-    let nums: Vec<_> = input
-        .lines()
-        .map(|s| u32::from_str_radix(s, 2).unwrap())
-        .collect();
+    let gamma = report.gamma(TieBreak::PreferOne);
+    let epsilon = report.epsilon(TieBreak::PreferOne);
 
-    let mut gamma: u32 = 0;
-    let mut epsilon: u32 = 0;
+    gamma.to_biguint() * epsilon.to_biguint()
+}
+
+fn solve_part2(input: &str) -> BigUint {
+    let report = Report::parse(input);
+    let index = report.rating_index();
+
+    let oxygen = index.oxygen_rating(TieBreak::PreferOne).unwrap();
+    let scrubber = index.scrubber_rating(TieBreak::PreferOne).unwrap();
+
+    oxygen.to_biguint() * scrubber.to_biguint()
+}
+
+/// Decides which bit counts as the most common one when there are exactly as
+/// many ones as zeroes. The least common bit is always the other one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreak {
+    PreferOne,
+    PreferZero,
+}
+
+impl TieBreak {
+    /// Picks the most common bit given how many of each there are.
+    pub fn most_common(&self, ones: usize, zeroes: usize) -> bool {
+        match ones.cmp(&zeroes) {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => *self == TieBreak::PreferOne,
+        }
+    }
+}
 
-    for idx in 0..bit_width {
-        let mask: u32 = 1 << idx;
+/// A binary number of any width, stored as 64-bit words with the least
+/// significant word first. Bit 0 is the least significant bit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bits {
+    words: Vec<u64>,
+    width: usize,
+}
 
-        if count_mask(&nums, mask) > nums.len() / 2 {
-            gamma |= mask;
+impl Bits {
+    pub fn zero(width: usize) -> Bits {
+        Bits {
+            words: vec![0; words_for(width)],
+            width,
+        }
+    }
+
+    /// Parses a string of 0s and 1s, most significant bit first.
+    ///
+    /// ```
+    /// use aoc::day03::Bits;
+    /// let bits = Bits::parse("10110").unwrap();
+    /// assert_eq!(bits.width(), 5);
+    /// assert!(!bits.get(0) && bits.get(4));
+    /// assert_eq!(Bits::parse("10210"), None);
+    /// ```
+    pub fn parse(s: &str) -> Option<Bits> {
+        let mut bits = Bits::zero(s.len());
+
+        for (idx, c) in s.bytes().rev().enumerate() {
+            match c {
+                b'1' => bits.set(idx, true),
+                b'0' => {}
+                _ => return None,
+            }
+        }
+
+        Some(bits)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get(&self, idx: usize) -> bool {
+        self.words[idx / 64] & (1 << (idx % 64)) > 0
+    }
+
+    pub fn set(&mut self, idx: usize, value: bool) {
+        let mask = 1 << (idx % 64);
+        if value {
+            self.words[idx / 64] |= mask;
         } else {
-            epsilon |= mask;
+            self.words[idx / 64] &= !mask;
         }
     }
 
-    gamma * epsilon
+    pub fn to_biguint(&self) -> BigUint {
+        let digits: Vec<u32> = self
+            .words
+            .iter()
+            .flat_map(|&w| [w as u32, (w >> 32) as u32])
+            .collect();
+        BigUint::new(digits)
+    }
 }
 
-fn solve_part2(input: &str) -> u32 {
-    let bit_width = input.lines().next().unwrap().len();
+/// A diagnostic report. The numbers are stored one column of words at a time
+/// (all the lowest words, then all the next words, and so on) so that the
+/// bits in any position can be counted with `count_mask`.
+#[derive(Clone, Debug)]
+pub struct Report {
+    columns: Vec<Vec<u64>>,
+    width: usize,
+    len: usize,
+}
 
-    let nums: Vec<_> = input
-        .lines()
-        .map(|s| u32::from_str_radix(s, 2).unwrap())
-        .collect();
+impl Report {
+    /// Parses one binary number per line. Every line must have the same
+    /// width, which can be as wide as you like.
+    pub fn parse(input: &str) -> Report {
+        let nums: Vec<Bits> = input
+            .lines()
+            .map(|line| Bits::parse(line.trim()).expect("Report must be binary"))
+            .collect();
 
-    let mut oxygen_ratings = nums.clone();
-    let mut scrubber_ratings = nums;
+        let width = nums.first().map_or(0, |n| n.width());
+        assert!(
+            nums.iter().all(|n| n.width() == width),
+            "Every number in the report must have the same width"
+        );
 
-    for idx in (0..bit_width).rev() {
-        let mask: u32 = 1 << idx;
+        let columns = (0..words_for(width))
+            .map(|w| nums.iter().map(|n| n.words[w]).collect())
+            .collect();
 
-        if oxygen_ratings.len() > 1 {
-            if count_mask(&oxygen_ratings, mask) * 2 >= oxygen_ratings.len() {
-                oxygen_ratings.retain(|&n| n & mask > 0);
-            } else {
-                oxygen_ratings.retain(|&n| n & mask == 0);
+        Report {
+            columns,
+            width,
+            len: nums.len(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the `n`th number in the report.
+    pub fn get(&self, n: usize) -> Bits {
+        Bits {
+            words: self.columns.iter().map(|col| col[n]).collect(),
+            width: self.width,
+        }
+    }
+
+    /// Counts how many numbers have a one in position `idx`.
+    pub fn count_ones(&self, idx: usize) -> usize {
+        count_mask(&self.columns[idx / 64], 1 << (idx % 64))
+    }
+
+    /// Counts the ones in every position in a single pass over the report.
+    pub fn bit_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0; self.width];
+
+        for (w, col) in self.columns.iter().enumerate() {
+            let counts = &mut counts[w * 64..(w * 64 + 64).min(self.width)];
+
+            // Keep bit-sliced counters: bit `b` of `planes[k]` is bit `k` of the
+            // count for position `b`. Adding a word is then a ripple-carry add
+            // which updates all 64 positions at once.
+            for chunk in col.chunks(u16::MAX as usize) {
+                let mut planes = [0u64; 16];
+                for &word in chunk {
+                    let mut carry = word;
+                    for plane in planes.iter_mut() {
+                        let sum = *plane ^ carry;
+                        carry &= *plane;
+                        *plane = sum;
+                    }
+                }
+
+                for (k, &plane) in planes.iter().enumerate() {
+                    for (bit, count) in counts.iter_mut().enumerate() {
+                        *count += (((plane >> bit) & 1) as usize) << k;
+                    }
+                }
             }
         }
 
-        if scrubber_ratings.len() > 1 {
-            if count_mask(&scrubber_ratings, mask) * 2 >= scrubber_ratings.len() {
-                scrubber_ratings.retain(|&n| n & mask == 0);
+        counts
+    }
+
+    pub fn most_common(&self, idx: usize, tie: TieBreak) -> bool {
+        let ones = self.count_ones(idx);
+        tie.most_common(ones, self.len - ones)
+    }
+
+    pub fn gamma(&self, tie: TieBreak) -> Bits {
+        let mut gamma = Bits::zero(self.width);
+        for (idx, ones) in self.bit_counts().into_iter().enumerate() {
+            gamma.set(idx, tie.most_common(ones, self.len - ones));
+        }
+        gamma
+    }
+
+    pub fn epsilon(&self, tie: TieBreak) -> Bits {
+        let mut epsilon = Bits::zero(self.width);
+        for (idx, ones) in self.bit_counts().into_iter().enumerate() {
+            epsilon.set(idx, !tie.most_common(ones, self.len - ones));
+        }
+        epsilon
+    }
+
+    /// Sorts the report so that ratings can be found quickly. If you need
+    /// both ratings, build this once and ask it for both.
+    pub fn rating_index(&self) -> RatingIndex {
+        let words = self.columns.len();
+
+        // Lay the numbers out with the most significant word first so that
+        // comparing two rows compares the numbers.
+        let mut unsorted = Vec::with_capacity(self.len * words);
+        for n in 0..self.len {
+            unsorted.extend(self.columns.iter().rev().map(|col| col[n]));
+        }
+        let row = |n: usize| &unsorted[n * words..(n + 1) * words];
+
+        let mut order: Vec<usize> = (0..self.len).collect();
+        order.sort_unstable_by(|&a, &b| row(a).cmp(row(b)));
+
+        let mut rows = Vec::with_capacity(self.len * words);
+        for n in order {
+            rows.extend(row(n).iter().rev());
+        }
+
+        RatingIndex {
+            rows,
+            words,
+            width: self.width,
+        }
+    }
+
+    pub fn oxygen_rating(&self, tie: TieBreak) -> Option<Bits> {
+        self.rating_index().oxygen_rating(tie)
+    }
+
+    pub fn scrubber_rating(&self, tie: TieBreak) -> Option<Bits> {
+        self.rating_index().scrubber_rating(tie)
+    }
+}
+
+/// The numbers from a report in sorted order, stored back to back. Every set
+/// of numbers sharing a prefix is a contiguous range, and within that range
+/// the ones with a 0 in the next position all come before the ones with a 1.
+/// Narrowing the candidates by one bit is then a binary search for that split.
+#[derive(Clone, Debug)]
+pub struct RatingIndex {
+    rows: Vec<u64>,
+    words: usize,
+    width: usize,
+}
+
+impl RatingIndex {
+    pub fn oxygen_rating(&self, tie: TieBreak) -> Option<Bits> {
+        self.rating(|ones, zeroes| tie.most_common(ones, zeroes))
+    }
+
+    pub fn scrubber_rating(&self, tie: TieBreak) -> Option<Bits> {
+        self.rating(|ones, zeroes| !tie.most_common(ones, zeroes))
+    }
+
+    fn len(&self) -> usize {
+        self.rows.len() / self.words.max(1)
+    }
+
+    fn has_bit(&self, n: usize, idx: usize) -> bool {
+        self.rows[n * self.words + idx / 64] & (1 << (idx % 64)) > 0
+    }
+
+    /// Narrows the candidates down from the most significant bit, keeping
+    /// the bit chosen by `keep` given the count of ones and zeroes, until a
+    /// single number is left. If `keep` picks a bit which none of the
+    /// candidates have, nothing is left and there's no rating.
+    fn rating<F>(&self, keep: F) -> Option<Bits>
+    where
+        F: Fn(usize, usize) -> bool,
+    {
+        let (mut lo, mut hi) = (0, self.len());
+
+        for idx in (0..self.width).rev() {
+            if hi - lo <= 1 {
+                break;
+            }
+
+            // Binary search for the first candidate with a 1 in this position.
+            let (mut left, mut right) = (lo, hi);
+            while left < right {
+                let mid = left + (right - left) / 2;
+                if self.has_bit(mid, idx) {
+                    right = mid;
+                } else {
+                    left = mid + 1;
+                }
+            }
+
+            if keep(hi - left, left - lo) {
+                lo = left;
             } else {
-                scrubber_ratings.retain(|&n| n & mask > 0);
+                hi = left;
             }
         }
+
+        if lo == hi {
+            return None;
+        }
+
+        Some(Bits {
+            words: self.rows[lo * self.words..(lo + 1) * self.words].to_vec(),
+            width: self.width,
+        })
     }
+}
 
-    oxygen_ratings[0] * scrubber_ratings[0]
+fn words_for(width: usize) -> usize {
+    width.div_ceil(64)
 }
 
 fn count_mask<T: PrimInt>(nums: &[T], mask: T) -> usize {
@@ -82,11 +356,198 @@ mod test {
 
     #[test]
     fn verify_example_input_part1() {
-        assert_eq!(198, solve_part1(example_input()));
+        assert_eq!(BigUint::from(198u32), solve_part1(example_input()));
     }
 
     #[test]
     fn verify_example_input_part2() {
-        assert_eq!(230, solve_part2(example_input()));
+        assert_eq!(BigUint::from(230u32), solve_part2(example_input()));
+    }
+
+    #[test]
+    fn handles_wide_reports() {
+        // The example report, with 70 extra leading bits which are all ones
+        // in every number so they can't affect which numbers get kept.
+        let prefix = "1".repeat(70);
+        let input: String = example_input()
+            .lines()
+            .map(|line| format!("{}{}\n", prefix, line))
+            .collect();
+        let report = Report::parse(&input);
+        assert_eq!(75, report.width());
+
+        let oxygen = report.oxygen_rating(TieBreak::PreferOne).unwrap();
+        let expected = (BigUint::from(1u32) << 75) - BigUint::from(32u32) + BigUint::from(23u32);
+        assert_eq!(expected, oxygen.to_biguint());
+        assert_eq!(
+            BigUint::from(9u32),
+            report.epsilon(TieBreak::PreferOne).to_biguint()
+        );
+    }
+
+    #[test]
+    fn counts_all_bits_at_once() {
+        let report = Report::parse(example_input());
+        let counts: Vec<_> = (0..report.width()).map(|i| report.count_ones(i)).collect();
+        assert_eq!(counts, report.bit_counts());
+    }
+
+    #[test]
+    fn indexed_ratings_match_retain() {
+        // Random numbers rarely share every remaining bit, so add a report
+        // where the scrubber rating filters out every candidate.
+        let mut empty = 0;
+        for report in [
+            bench::generate_report(500, 40),
+            Report::parse("11\n11\n10\n"),
+        ] {
+            for tie in [TieBreak::PreferOne, TieBreak::PreferZero] {
+                let index = report.rating_index();
+                let oxygen = index.oxygen_rating(tie);
+                let scrubber = index.scrubber_rating(tie);
+                assert_eq!(
+                    bench::retain_rating(&report, |r, i| r.most_common(i, tie)),
+                    oxygen
+                );
+                assert_eq!(
+                    bench::retain_rating(&report, |r, i| !r.most_common(i, tie)),
+                    scrubber
+                );
+                empty += oxygen.is_none() as usize + scrubber.is_none() as usize;
+            }
+        }
+        assert!(empty > 0);
+    }
+
+    #[test]
+    fn breaks_ties_by_policy() {
+        let report = Report::parse("10\n01\n");
+        assert!(report.most_common(0, TieBreak::PreferOne));
+        assert!(!report.most_common(0, TieBreak::PreferZero));
+        assert_eq!(Bits::parse("10"), report.oxygen_rating(TieBreak::PreferOne));
+        assert_eq!(
+            Bits::parse("01"),
+            report.oxygen_rating(TieBreak::PreferZero)
+        );
+    }
+
+    #[test]
+    fn has_no_rating_when_every_candidate_is_filtered_out() {
+        // Every number starts with 1, so the scrubber keeps the zeroes and
+        // there are none.
+        let report = Report::parse("11\n11\n10\n");
+        assert_eq!(None, report.scrubber_rating(TieBreak::PreferOne));
+        assert_eq!(Bits::parse("11"), report.oxygen_rating(TieBreak::PreferOne));
+
+        assert_eq!(None, Report::parse("").oxygen_rating(TieBreak::PreferOne));
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    #[cfg(feature = "bench")]
+    extern crate test;
+
+    use super::*;
+    use crate::XorShift;
+    #[cfg(feature = "bench")]
+    use test::Bencher;
+
+    /// Generates a report of `len` pseudo-random numbers, `width` bits wide.
+    pub fn generate_report(len: usize, width: usize) -> Report {
+        let mut rng = XorShift::new(0x2545_f491_4f6c_dd1d);
+        let mut input = String::new();
+
+        for _ in 0..len {
+            for _ in 0..width {
+                input.push(if rng.next_u64() & 1 == 1 { '1' } else { '0' });
+            }
+            input.push('\n');
+        }
+
+        Report::parse(&input)
+    }
+
+    /// The original approach to the ratings, which re-counts and filters
+    /// every remaining number for each bit. Kept as a baseline.
+    pub fn retain_rating<F>(report: &Report, keep: F) -> Option<Bits>
+    where
+        F: Fn(&Report, usize) -> bool,
+    {
+        let mut remaining = report.clone();
+
+        for idx in (0..report.width()).rev() {
+            if remaining.len() <= 1 {
+                break;
+            }
+
+            let value = keep(&remaining, idx);
+            let mask = 1 << (idx % 64);
+            let flags: Vec<bool> = remaining.columns[idx / 64]
+                .iter()
+                .map(|&w| (w & mask > 0) == value)
+                .collect();
+
+            for col in remaining.columns.iter_mut() {
+                let mut flag = flags.iter();
+                col.retain(|_| *flag.next().unwrap());
+            }
+            remaining.len = flags.iter().filter(|&&f| f).count();
+        }
+
+        (!remaining.is_empty()).then(|| remaining.get(0))
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn gamma_per_bit(b: &mut Bencher) {
+        let report = generate_report(100_000, 48);
+        b.iter(|| {
+            let mut gamma = Bits::zero(report.width());
+            for idx in 0..report.width() {
+                gamma.set(idx, report.most_common(idx, TieBreak::PreferOne));
+            }
+            gamma
+        });
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn gamma_bit_counts(b: &mut Bencher) {
+        let report = generate_report(100_000, 48);
+        b.iter(|| report.gamma(TieBreak::PreferOne));
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn ratings_retain(b: &mut Bencher) {
+        let report = generate_report(100_000, 48);
+        b.iter(|| {
+            let tie = TieBreak::PreferOne;
+            (
+                retain_rating(&report, |r, i| r.most_common(i, tie)),
+                retain_rating(&report, |r, i| !r.most_common(i, tie)),
+            )
+        });
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn rating_index_build(b: &mut Bencher) {
+        let report = generate_report(100_000, 48);
+        b.iter(|| report.rating_index());
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn ratings_indexed(b: &mut Bencher) {
+        let report = generate_report(100_000, 48);
+        let index = report.rating_index();
+        b.iter(|| {
+            (
+                index.oxygen_rating(TieBreak::PreferOne),
+                index.scrubber_rating(TieBreak::PreferOne),
+            )
+        });
     }
 }
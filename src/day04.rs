@@ -1,3 +1,5 @@
+use std::collections::{HashMap, VecDeque};
+
 pub fn run() {
     let input = puzzle_input();
     println!("day04.part1.solution = {}", solve_part1(input));
@@ -6,49 +8,130 @@ pub fn run() {
 
 fn solve_part1(input: &str) -> u32 {
     let (nums, boards) = parse_input(input);
-    let win = boards
-        .iter()
-        .filter_map(|board| play(&mut board.clone(), &nums))
-        .min_by_key(|x| x.moves)
-        .unwrap();
+    let game = Game::new(nums, boards, STANDARD_PATTERNS);
 
-    win.score * nums[win.moves]
+    game.wins().next().unwrap().score
 }
 
 fn solve_part2(input: &str) -> u32 {
     let (nums, boards) = parse_input(input);
-    let win = boards
-        .iter()
-        .filter_map(|board| play(&mut board.clone(), &nums))
-        .max_by_key(|x| x.moves)
-        .unwrap();
+    let game = Game::new(nums, boards, STANDARD_PATTERNS);
+
+    game.wins().last().unwrap().score
+}
+
+/// The ways a board can win. Each pattern is made up of one or more groups of
+/// cells, and the board wins once every cell in any one group is marked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WinPattern {
+    Rows,
+    Columns,
+    /// Both diagonals. Only square boards have diagonals.
+    Diagonals,
+    FourCorners,
+    FullCard,
+}
+
+/// The patterns from the puzzle: complete any row or column.
+pub const STANDARD_PATTERNS: &[WinPattern] = &[WinPattern::Rows, WinPattern::Columns];
 
-    win.score * nums[win.moves]
+impl WinPattern {
+    /// Lists the groups of cells (as indices into a row-major board) which
+    /// win under this pattern on a board of the given size.
+    pub fn lines(&self, rows: usize, cols: usize) -> Vec<Vec<usize>> {
+        match self {
+            WinPattern::Rows => (0..rows)
+                .map(|r| (0..cols).map(|c| r * cols + c).collect())
+                .collect(),
+            WinPattern::Columns => (0..cols)
+                .map(|c| (0..rows).map(|r| r * cols + c).collect())
+                .collect(),
+            WinPattern::Diagonals if rows == cols => vec![
+                (0..rows).map(|i| i * cols + i).collect(),
+                (0..rows).map(|i| i * cols + (cols - 1 - i)).collect(),
+            ],
+            WinPattern::Diagonals => vec![],
+            WinPattern::FourCorners => {
+                let mut corners = vec![0, cols - 1, (rows - 1) * cols, rows * cols - 1];
+                corners.sort_unstable();
+                corners.dedup();
+                vec![corners]
+            }
+            WinPattern::FullCard => vec![(0..rows * cols).collect()],
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
-struct BingoBoard {
+pub struct BingoBoard {
     spaces: Vec<u32>,
     drawn: Vec<bool>,
+    rows: usize,
+    cols: usize,
 }
 
 impl BingoBoard {
-    pub fn from_spaces(spaces: &[u32]) -> BingoBoard {
-        assert_eq!(spaces.len(), 25);
+    pub fn new(rows: usize, cols: usize, spaces: &[u32]) -> BingoBoard {
+        assert!(rows > 0 && cols > 0, "Boards can't be empty");
+        assert_eq!(spaces.len(), rows * cols);
         BingoBoard {
             spaces: spaces.into(),
-            drawn: vec![false; 25],
+            drawn: vec![false; rows * cols],
+            rows,
+            cols,
         }
     }
 
-    pub fn is_winning(&self) -> bool {
-        (0..5).any(|i| complete(i, 5, &self.drawn) || complete(i * 5, 1, &self.drawn))
+    /// Parses a board with one row per line. The size of the board comes
+    /// from the input, but every row must be the same length.
+    ///
+    /// ```
+    /// use aoc::day04::BingoBoard;
+    /// let board = BingoBoard::parse("1 2 3\n4 5 6\n");
+    /// assert_eq!((board.rows(), board.cols()), (2, 3));
+    /// ```
+    pub fn parse(block: &str) -> BingoBoard {
+        let rows: Vec<Vec<u32>> = block
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split_ascii_whitespace()
+                    .map(|n| n.parse::<u32>().unwrap())
+                    .collect()
+            })
+            .collect();
+
+        let cols = rows.first().map_or(0, |row| row.len());
+        assert!(
+            rows.iter().all(|row| row.len() == cols),
+            "Every row of a board must be the same length"
+        );
+
+        BingoBoard::new(rows.len(), cols, &rows.concat())
     }
 
-    pub fn mark(&mut self, num: u32) {
-        if let Some(i) = self.spaces.iter().position(|x| *x == num) {
-            self.drawn[i] = true;
-        }
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn is_winning(&self, patterns: &[WinPattern]) -> bool {
+        patterns.iter().any(|pattern| {
+            pattern
+                .lines(self.rows, self.cols)
+                .iter()
+                .any(|line| line.iter().all(|&i| self.drawn[i]))
+        })
+    }
+
+    /// Marks `num` if it's on the board, and returns which cell it was in.
+    pub fn mark(&mut self, num: u32) -> Option<usize> {
+        let i = self.spaces.iter().position(|x| *x == num)?;
+        self.drawn[i] = true;
+        Some(i)
     }
 
     pub fn score(&self) -> u32 {
@@ -61,51 +144,226 @@ impl BingoBoard {
     }
 }
 
-fn complete(idx: usize, inc: usize, drawn: &[bool]) -> bool {
-    (idx..).step_by(inc).take(5).all(|i| drawn[i])
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Win {
+    pub board: usize,
+    /// The index of the draw on which the board won.
+    pub draw: usize,
+    /// The sum of the unmarked numbers times the number just drawn.
+    pub score: u32,
 }
 
-struct Win {
-    pub moves: usize,
-    pub score: u32,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Drawn { draw: usize, number: u32 },
+    Marked { board: usize, cell: usize },
+    Won(Win),
+}
+
+/// Plays every board at once, producing a log of what happens on each draw.
+/// Boards drop out of the game once they win, and the game ends when there
+/// are no numbers left or every board has won. When several boards win on
+/// the same draw, they are reported in the order the boards were given.
+///
+/// ```
+/// use aoc::day04::{BingoBoard, Event, Game, STANDARD_PATTERNS};
+/// let boards = vec![BingoBoard::parse("1 2\n3 4"), BingoBoard::parse("2 4\n5 6")];
+/// let game = Game::new(vec![2, 4], boards, STANDARD_PATTERNS);
+/// let wins: Vec<_> = game.wins().map(|w| (w.board, w.score)).collect();
+/// assert_eq!(wins, vec![(0, 16), (1, 44)]);
+/// ```
+pub struct Game {
+    nums: Vec<u32>,
+    boards: Vec<BingoBoard>,
+    /// Every cell holding each number, as (board, cell) pairs.
+    index: HashMap<u32, Vec<(usize, usize)>>,
+    layouts: Vec<Layout>,
+    board_layouts: Vec<usize>,
+    /// How many cells of each of its lines each board has marked.
+    hits: Vec<Vec<usize>>,
+    unmarked: Vec<u32>,
+    won: Vec<bool>,
+    remaining: usize,
+    next_draw: usize,
+    pending: VecDeque<Event>,
+}
+
+/// The winning lines for one size of board, and which lines each cell is in.
+struct Layout {
+    rows: usize,
+    cols: usize,
+    line_lengths: Vec<usize>,
+    cell_lines: Vec<Vec<usize>>,
+}
+
+impl Layout {
+    fn new(rows: usize, cols: usize, patterns: &[WinPattern]) -> Layout {
+        let lines: Vec<_> = patterns.iter().flat_map(|p| p.lines(rows, cols)).collect();
+
+        let mut cell_lines = vec![vec![]; rows * cols];
+        for (id, line) in lines.iter().enumerate() {
+            for &cell in line {
+                cell_lines[cell].push(id);
+            }
+        }
+
+        Layout {
+            rows,
+            cols,
+            line_lengths: lines.iter().map(|line| line.len()).collect(),
+            cell_lines,
+        }
+    }
+}
+
+impl Game {
+    pub fn new(nums: Vec<u32>, boards: Vec<BingoBoard>, patterns: &[WinPattern]) -> Game {
+        let mut index: HashMap<u32, Vec<(usize, usize)>> = HashMap::new();
+        let mut layouts: Vec<Layout> = vec![];
+        let mut board_layouts = vec![];
+        let mut hits = vec![];
+        let mut unmarked = vec![];
+
+        for (board, b) in boards.iter().enumerate() {
+            for (cell, &num) in b.spaces.iter().enumerate() {
+                index.entry(num).or_default().push((board, cell));
+            }
+
+            let layout = match layouts
+                .iter()
+                .position(|l| (l.rows, l.cols) == (b.rows, b.cols))
+            {
+                Some(layout) => layout,
+                None => {
+                    layouts.push(Layout::new(b.rows, b.cols, patterns));
+                    layouts.len() - 1
+                }
+            };
+            board_layouts.push(layout);
+            hits.push(vec![0; layouts[layout].line_lengths.len()]);
+            unmarked.push(b.score());
+        }
+
+        Game {
+            nums,
+            index,
+            layouts,
+            board_layouts,
+            hits,
+            unmarked,
+            won: vec![false; boards.len()],
+            remaining: boards.len(),
+            boards,
+            next_draw: 0,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Plays the rest of the game and returns the wins in the order they
+    /// happen, so `wins().nth(n)` is the board which wins (n+1)th.
+    pub fn wins(self) -> impl Iterator<Item = Win> {
+        self.filter_map(|event| match event {
+            Event::Won(win) => Some(win),
+            _ => None,
+        })
+    }
+
+    /// Draws the next number. Only the boards which contain the number are
+    /// touched, and only the lines through the marked cell are checked.
+    fn draw(&mut self) -> Option<()> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let draw = self.next_draw;
+        let number = *self.nums.get(draw)?;
+        self.next_draw += 1;
+        self.pending.push_back(Event::Drawn { draw, number });
+
+        let cells = match self.index.get(&number) {
+            Some(cells) => cells,
+            None => return Some(()),
+        };
+
+        for &(board, cell) in cells {
+            let b = &mut self.boards[board];
+            if self.won[board] || b.drawn[cell] {
+                continue;
+            }
+
+            b.drawn[cell] = true;
+            self.unmarked[board] -= number;
+            self.pending.push_back(Event::Marked { board, cell });
+
+            let layout = &self.layouts[self.board_layouts[board]];
+            let mut winning = false;
+            for &line in &layout.cell_lines[cell] {
+                self.hits[board][line] += 1;
+                winning |= self.hits[board][line] == layout.line_lengths[line];
+            }
+
+            if winning {
+                self.won[board] = true;
+                self.remaining -= 1;
+                self.pending.push_back(Event::Won(Win {
+                    board,
+                    draw,
+                    score: self.unmarked[board] * number,
+                }));
+            }
+        }
+
+        Some(())
+    }
+}
+
+impl Iterator for Game {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        while self.pending.is_empty() {
+            self.draw()?;
+        }
+        self.pending.pop_front()
+    }
 }
 
 fn puzzle_input() -> &'static str {
     include_str!("../inputs/day04.txt")
 }
 
+/// Parses the drawn numbers from the first line, followed by boards which
+/// are separated from each other by blank lines.
 fn parse_input(input: &str) -> (Vec<u32>, Vec<BingoBoard>) {
     let (nums_line, cards_input) = input.split_once("\n").unwrap();
 
     let nums = nums_line
+        .trim()
         .split(',')
         .map(|n| n.parse::<u32>().unwrap())
         .collect();
-    let board_nums: Vec<_> = cards_input
-        .split_ascii_whitespace()
-        .map(|n| n.parse::<u32>().unwrap())
-        .collect();
-    let boards = board_nums.chunks(25).map(BingoBoard::from_spaces).collect();
 
-    (nums, boards)
-}
-
-fn play(board: &mut BingoBoard, nums: &[u32]) -> Option<Win> {
-    for (i, &num) in nums.iter().enumerate() {
-        board.mark(num);
-        if board.is_winning() {
-            return Some(Win {
-                moves: i,
-                score: board.score(),
-            });
+    let mut boards = vec![];
+    let mut block = String::new();
+    for line in cards_input.lines().chain(std::iter::once("")) {
+        if line.trim().is_empty() {
+            if !block.is_empty() {
+                boards.push(BingoBoard::parse(&block));
+                block.clear();
+            }
+        } else {
+            block.push_str(line);
+            block.push('\n');
         }
     }
-    None
+
+    (nums, boards)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::XorShift;
 
     fn example_input() -> &'static str {
         include_str!("../inputs/day04_example.txt")
@@ -116,6 +374,7 @@ mod test {
         let (nums, boards) = parse_input(example_input());
         assert_eq!(27, nums.len());
         assert_eq!(3, boards.len());
+        assert!(boards.iter().all(|b| b.rows() == 5 && b.cols() == 5));
     }
 
     #[test]
@@ -127,4 +386,131 @@ mod test {
     fn verify_example_input_part2() {
         assert_eq!(1924, solve_part2(example_input()))
     }
+
+    #[test]
+    fn supports_other_sizes_and_patterns() {
+        let input = "5,1,9,3,7\n\n1 2 3\n4 5 6\n7 8 9\n\n1 2\n3 4\n5 6\n";
+        let (nums, boards) = parse_input(input);
+        assert_eq!((3, 3), (boards[0].rows(), boards[0].cols()));
+        assert_eq!((3, 2), (boards[1].rows(), boards[1].cols()));
+
+        let wins = |patterns: &[WinPattern]| -> Vec<Win> {
+            Game::new(nums.clone(), boards.clone(), patterns)
+                .wins()
+                .collect()
+        };
+
+        let diagonals = wins(&[WinPattern::Diagonals]);
+        assert_eq!(1, diagonals.len());
+        assert_eq!((0, 2), (diagonals[0].board, diagonals[0].draw));
+
+        let corners = wins(&[WinPattern::FourCorners]);
+        assert_eq!((0, 4), (corners[0].board, corners[0].draw));
+
+        let columns = wins(&[WinPattern::Columns]);
+        assert_eq!(1, columns.len());
+        assert_eq!(
+            Win {
+                board: 1,
+                draw: 3,
+                score: (2 + 4 + 6) * 3
+            },
+            columns[0]
+        );
+
+        assert!(wins(&[WinPattern::FullCard]).is_empty());
+    }
+
+    #[test]
+    fn logs_every_event() {
+        let boards = vec![BingoBoard::parse("1 2\n3 4"), BingoBoard::parse("4 1\n5 6")];
+        let events: Vec<_> = Game::new(vec![9, 1, 4, 3], boards, STANDARD_PATTERNS).collect();
+
+        assert_eq!(
+            vec![
+                Event::Drawn { draw: 0, number: 9 },
+                Event::Drawn { draw: 1, number: 1 },
+                Event::Marked { board: 0, cell: 0 },
+                Event::Marked { board: 1, cell: 1 },
+                Event::Drawn { draw: 2, number: 4 },
+                Event::Marked { board: 0, cell: 3 },
+                Event::Marked { board: 1, cell: 0 },
+                Event::Won(Win {
+                    board: 1,
+                    draw: 2,
+                    score: 44
+                }),
+                Event::Drawn { draw: 3, number: 3 },
+                Event::Marked { board: 0, cell: 2 },
+                Event::Won(Win {
+                    board: 0,
+                    draw: 3,
+                    score: 6
+                }),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn keeps_ties_on_the_same_draw() {
+        let (nums, boards) = parse_input(example_input());
+        let mut boards = boards;
+        boards.push(boards[2].clone());
+
+        let wins: Vec<_> = Game::new(nums, boards, STANDARD_PATTERNS).wins().collect();
+        assert_eq!(4, wins.len());
+        assert_eq!((2, 3), (wins[0].board, wins[1].board));
+        assert_eq!(wins[0].draw, wins[1].draw);
+        assert_eq!(4512, wins[1].score);
+    }
+
+    /// Generates `count` random 5x5 boards, and a random order to draw all
+    /// the numbers which could be on them.
+    fn generate_game(count: usize) -> (Vec<u32>, Vec<BingoBoard>) {
+        let mut rng = XorShift::new(0x9e37_79b9_7f4a_7c15);
+        let mut shuffled = |mut nums: Vec<u32>| {
+            for i in (1..nums.len()).rev() {
+                nums.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+            }
+            nums
+        };
+
+        let boards = (0..count)
+            .map(|_| BingoBoard::new(5, 5, &shuffled((0..100).collect())[..25]))
+            .collect();
+        (shuffled((0..100).collect()), boards)
+    }
+
+    #[test]
+    fn indexed_game_matches_naive_play() {
+        let (nums, boards) = generate_game(500);
+        let patterns = &[WinPattern::Rows, WinPattern::Diagonals];
+
+        let mut naive: Vec<Win> = vec![];
+        for (board, b) in boards.iter().enumerate() {
+            let mut b = b.clone();
+            for (draw, &num) in nums.iter().enumerate() {
+                b.mark(num);
+                if b.is_winning(patterns) {
+                    let score = b.score() * num;
+                    naive.push(Win { board, draw, score });
+                    break;
+                }
+            }
+        }
+        naive.sort_by_key(|w| (w.draw, w.board));
+
+        let wins: Vec<_> = Game::new(nums, boards, patterns).wins().collect();
+        assert_eq!(naive, wins);
+    }
+
+    #[test]
+    fn plays_many_boards() {
+        let (nums, boards) = generate_game(20_000);
+        assert_eq!(
+            20_000,
+            Game::new(nums, boards, STANDARD_PATTERNS).wins().count()
+        );
+    }
 }
@@ -1,4 +1,6 @@
-use std::collections::HashSet;
+use num::integer::gcd;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 
 pub fn run() {
     let input = puzzle_input();
@@ -8,36 +10,168 @@ pub fn run() {
 
 fn solve_part1(input: &str) -> usize {
     let line_segments = parse_input(input);
+    let map = VentMap::from_segments(&line_segments, LineSegment::points_covered_hv);
 
-    let mut points: Vec<_> = line_segments
-        .iter()
-        .map(|x| x.points_covered_hv())
-        .flatten()
-        .collect();
-    points.sort_unstable();
-
-    let intersections: HashSet<_> = points.windows(2).filter(|w| w[0] == w[1]).collect();
-
-    intersections.len()
+    map.covered_at_least(2)
 }
 
 fn solve_part2(input: &str) -> usize {
     let line_segments = parse_input(input);
+    let map = VentMap::from_segments(&line_segments, LineSegment::points_covered);
 
-    let mut points: Vec<_> = line_segments
-        .iter()
-        .map(|x| x.points_covered())
-        .flatten()
-        .collect();
-    points.sort_unstable();
+    map.covered_at_least(2)
+}
+
+/// Bounding boxes with up to this many points may be stored densely.
+const DENSE_LIMIT: usize = 1 << 24;
+
+/// Counts how many times each point is covered by a line of vents.
+///
+/// Counts are kept in a flat grid over the bounding box when the box is
+/// small compared to the number of points being added, and in a hash map
+/// otherwise, so a few far-flung lines don't need a huge allocation.
+#[derive(Clone, Debug)]
+pub struct VentMap {
+    storage: Storage,
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+#[derive(Clone, Debug)]
+enum Storage {
+    Dense(Vec<u32>),
+    Sparse(HashMap<(i32, i32), u32>),
+}
+
+impl VentMap {
+    /// Adds up the points covered by each segment, as given by `points`,
+    /// which must stay within the box spanned by the segment's ends.
+    pub fn from_segments<F>(segments: &[LineSegment], points: F) -> VentMap
+    where
+        F: Fn(&LineSegment) -> Vec<(i32, i32)>,
+    {
+        let mut ends = segments.iter().flat_map(|seg| [seg.a, seg.b]);
+        let first = ends.next().unwrap_or((0, 0));
+        let (min, max) = ends.fold((first, first), |(min, max), (x, y)| {
+            ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y)))
+        });
+        let expected = segments.iter().map(LineSegment::steps).sum::<usize>() + segments.len();
+
+        let mut map = VentMap::with_bounds(min, max, expected);
+        for seg in segments {
+            for point in points(seg) {
+                map.add(point);
+            }
+        }
+        map
+    }
+
+    /// Creates an empty map for points within `min..=max`, expecting about
+    /// `expected` points to be added.
+    pub fn with_bounds(min: (i32, i32), max: (i32, i32), expected: usize) -> VentMap {
+        let width = (max.0 as i64 - min.0 as i64 + 1) as usize;
+        let height = (max.1 as i64 - min.1 as i64 + 1) as usize;
+        let area = width.saturating_mul(height);
+
+        let storage = if area <= DENSE_LIMIT && area <= expected.saturating_mul(8).max(1024) {
+            Storage::Dense(vec![0; area])
+        } else {
+            Storage::Sparse(HashMap::new())
+        };
+
+        VentMap { storage, min, max }
+    }
+
+    pub fn is_dense(&self) -> bool {
+        matches!(self.storage, Storage::Dense(_))
+    }
+
+    /// The smallest and largest corners of the area covered by the map.
+    pub fn bounds(&self) -> ((i32, i32), (i32, i32)) {
+        (self.min, self.max)
+    }
+
+    fn width(&self) -> usize {
+        (self.max.0 as i64 - self.min.0 as i64 + 1) as usize
+    }
+
+    /// Where `point` lives in the dense grid.
+    fn offset(&self, (x, y): (i32, i32)) -> usize {
+        (y - self.min.1) as usize * self.width() + (x - self.min.0) as usize
+    }
+
+    fn in_bounds(&self, (x, y): (i32, i32)) -> bool {
+        self.min.0 <= x && x <= self.max.0 && self.min.1 <= y && y <= self.max.1
+    }
+
+    /// Covers `point` one more time. Panics if it's outside the bounds.
+    pub fn add(&mut self, point: (i32, i32)) {
+        assert!(self.in_bounds(point), "{:?} is outside the map", point);
+
+        let offset = if self.is_dense() {
+            self.offset(point)
+        } else {
+            0
+        };
+        match &mut self.storage {
+            Storage::Dense(counts) => counts[offset] += 1,
+            Storage::Sparse(counts) => *counts.entry(point).or_insert(0) += 1,
+        }
+    }
 
-    let intersections: HashSet<_> = points.windows(2).filter(|w| w[0] == w[1]).collect();
+    /// How many times `point` is covered.
+    pub fn count(&self, point: (i32, i32)) -> u32 {
+        if !self.in_bounds(point) {
+            return 0;
+        }
 
-    intersections.len()
+        match &self.storage {
+            Storage::Dense(counts) => counts[self.offset(point)],
+            Storage::Sparse(counts) => counts.get(&point).copied().unwrap_or(0),
+        }
+    }
+
+    /// How many points are covered at least `n` times. Uncovered points are
+    /// never counted, even when `n` is 0.
+    pub fn covered_at_least(&self, n: u32) -> usize {
+        match &self.storage {
+            Storage::Dense(counts) => counts.iter().filter(|&&c| c >= n.max(1)).count(),
+            Storage::Sparse(counts) => counts.values().filter(|&&c| c >= n.max(1)).count(),
+        }
+    }
+
+    /// The counts for every point in the bounds, one row per `y` value.
+    pub fn grid(&self) -> Vec<Vec<u32>> {
+        (self.min.1..=self.max.1)
+            .map(|y| {
+                (self.min.0..=self.max.0)
+                    .map(|x| self.count((x, y)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Draws the map the way the puzzle does: `.` where there are no vents,
+    /// otherwise the number of lines covering the point (or `+` for more
+    /// than 9).
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for row in self.grid() {
+            for count in row {
+                out.push(match count {
+                    0 => '.',
+                    1..=9 => char::from_digit(count, 10).unwrap(),
+                    _ => '+',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
 }
 
-#[derive(Debug)]
-struct LineSegment {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineSegment {
     a: (i32, i32),
     b: (i32, i32),
 }
@@ -53,30 +187,112 @@ impl LineSegment {
             .unwrap()
     }
 
+    fn ends(&self) -> ((i64, i64), (i64, i64)) {
+        let widen = |(x, y): (i32, i32)| (x as i64, y as i64);
+        (widen(self.a), widen(self.b))
+    }
+
+    /// How many steps a line drawn one pixel at a time would take from one
+    /// end to the other, which is at least as many as any raster has
+    /// points after the first.
+    fn steps(&self) -> usize {
+        let (a, b) = self.ends();
+        (b.0 - a.0).unsigned_abs().max((b.1 - a.1).unsigned_abs()) as usize
+    }
+
+    /// The line through the segment, or None if the segment is a point.
+    fn line_key(&self) -> Option<LineKey> {
+        let (a, b) = self.ends();
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let steps = gcd(dx, dy);
+        if steps == 0 {
+            return None;
+        }
+
+        let (mut p, mut q) = ((dx / steps) as i128, (dy / steps) as i128);
+        if p < 0 || (p == 0 && q < 0) {
+            p = -p;
+            q = -q;
+        }
+
+        Some(LineKey {
+            p,
+            q,
+            c: q * a.0 as i128 - p * a.1 as i128,
+        })
+    }
+
+    /// The span of positions the segment covers along its line.
+    fn line_range(&self, key: &LineKey) -> (i128, i128) {
+        let (a, b) = self.ends();
+        let (ta, tb) = (key.position(a), key.position(b));
+        (ta.min(tb), ta.max(tb))
+    }
+
+    /// All the points on the segment which have integer coordinates, for
+    /// any slope. Same as `rasterize(Raster::Exact)`.
     pub fn points_covered(&self) -> Vec<(i32, i32)> {
-        let mut points = self.points_covered_hv();
-        points.extend(self.points_covered_diag());
-        points
+        self.rasterize(Raster::Exact)
     }
 
-    pub fn points_covered_diag(&self) -> Vec<(i32, i32)> {
-        let a = std::cmp::min(self.a, self.b);
-        let b = std::cmp::max(self.a, self.b);
+    pub fn rasterize(&self, mode: Raster) -> Vec<(i32, i32)> {
+        match mode {
+            Raster::Exact => self.lattice_points(),
+            Raster::Bresenham => self.bresenham_points(),
+        }
+    }
+
+    /// Steps from one end to the other by the smallest integer vector along
+    /// the segment, which is the difference between the ends divided by
+    /// their gcd.
+    fn lattice_points(&self) -> Vec<(i32, i32)> {
+        let (a, b) = self.ends();
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let steps = gcd(dx, dy);
+        if steps == 0 {
+            return vec![self.a];
+        }
 
-        let dx = b.0 - a.0;
-        let dy = b.1 - a.1;
+        let (step_x, step_y) = (dx / steps, dy / steps);
+        (0..=steps)
+            .map(|i| ((a.0 + i * step_x) as i32, (a.1 + i * step_y) as i32))
+            .collect()
+    }
 
-        if dx.abs() == dy.abs() {
-            let xs: Vec<_> = (a.0..=b.0).collect();
-            let ys: Vec<_> = if dy > 0 {
-                (a.1..=b.1).collect()
-            } else {
-                (b.1..=a.1).rev().collect()
-            };
-            xs.iter().cloned().zip(ys.iter().cloned()).collect()
-        } else {
-            vec![]
+    /// The nearest point to the segment in each column (or row, for steep
+    /// segments), so every segment covers a connected run of points. Always
+    /// starts from the smaller end so both directions give the same points.
+    fn bresenham_points(&self) -> Vec<(i32, i32)> {
+        let (a, b) = self.ends();
+        let start = std::cmp::min(a, b);
+        let end = std::cmp::max(a, b);
+
+        let (dx, dy) = ((end.0 - start.0).abs(), -(end.1 - start.1).abs());
+        let step_x = if start.0 < end.0 { 1 } else { -1 };
+        let step_y = if start.1 < end.1 { 1 } else { -1 };
+
+        let mut points = vec![];
+        let (mut x, mut y) = start;
+        let mut err = dx + dy;
+
+        loop {
+            points.push((x as i32, y as i32));
+            if (x, y) == end {
+                break;
+            }
+
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += step_y;
+            }
         }
+
+        points
     }
 
     pub fn points_covered_hv(&self) -> Vec<(i32, i32)> {
@@ -93,6 +309,524 @@ impl LineSegment {
     }
 }
 
+/// Counts the points covered by at least two segments, like
+/// `VentMap::from_segments(segments, LineSegment::points_covered)` would,
+/// but without visiting every point, so the coordinates can be as large as
+/// you like.
+///
+/// Segments on the same line are grouped together and their overlaps found
+/// by sorting their ends. That leaves each line covered by disjoint pieces,
+/// and pieces on different lines can only share single points. Those are
+/// found with a Bentley-Ottmann sweep, which keeps the pieces crossing the
+/// sweep line in order and only checks pieces which are next to each other
+/// in that order, taking O((n + k) log n) time for n segments meeting at k
+/// points.
+pub fn count_overlaps(segments: &[LineSegment]) -> usize {
+    let mut lines: HashMap<LineKey, Vec<(i128, i128)>> = HashMap::new();
+    let mut pieces = vec![];
+    for seg in segments {
+        match seg.line_key() {
+            Some(key) => lines.entry(key).or_default().push(seg.line_range(&key)),
+            None => pieces.push(Piece {
+                a: seg.ends().0,
+                b: seg.ends().0,
+                key: None,
+            }),
+        }
+    }
+
+    let mut overlaps: HashMap<LineKey, Vec<(i128, i128)>> = HashMap::new();
+    for (key, ranges) in lines {
+        for (lo, hi) in merge_ranges(&ranges) {
+            pieces.push(Piece {
+                a: key.point(lo),
+                b: key.point(hi),
+                key: Some(key),
+            });
+        }
+
+        let twice = covered_twice(ranges);
+        if !twice.is_empty() {
+            overlaps.insert(key, twice);
+        }
+    }
+
+    let collinear: usize = overlaps
+        .iter()
+        .flat_map(|(key, ranges)| ranges.iter().map(move |(lo, hi)| (hi - lo) / key.spacing()))
+        .map(|steps| steps as usize + 1)
+        .sum();
+
+    // Each point where pieces meet is covered twice, but it may be in the
+    // overlaps on one or more of those lines, in which case it has been
+    // counted once for each of them already.
+    let mut uncounted = 0;
+    let mut overcounted = 0;
+    sweep(&pieces, |point, meeting| {
+        let mut counted: Vec<LineKey> = vec![];
+        for key in meeting.iter().filter_map(|&idx| pieces[idx].key) {
+            let t = key.position(point);
+            let in_overlap = overlaps
+                .get(&key)
+                .is_some_and(|ranges| ranges.iter().any(|&(lo, hi)| lo <= t && t <= hi));
+            if in_overlap && !counted.contains(&key) {
+                counted.push(key);
+            }
+        }
+
+        match counted.len() {
+            0 => uncounted += 1,
+            n => overcounted += n - 1,
+        }
+    });
+
+    collinear + uncounted - overcounted
+}
+
+/// Part of a line covered by at least one segment, running from `a` to
+/// `b` with `a` first in (x, y) order. Pieces on the same line never touch.
+/// Segments which are single points are pieces on no line.
+#[derive(Clone, Copy, Debug)]
+struct Piece {
+    a: (i64, i64),
+    b: (i64, i64),
+    key: Option<LineKey>,
+}
+
+/// A point in the sweep, which can be anywhere that two pieces cross, so
+/// its coordinates are fractions: `x / d` and `y / d`, with `d > 0`.
+#[derive(Clone, Copy, Debug)]
+struct Point {
+    x: i128,
+    y: i128,
+    d: i128,
+}
+
+impl Point {
+    fn new((x, y): (i64, i64)) -> Point {
+        Point {
+            x: x as i128,
+            y: y as i128,
+            d: 1,
+        }
+    }
+
+    fn lattice(&self) -> Option<(i64, i64)> {
+        (self.x % self.d == 0 && self.y % self.d == 0)
+            .then(|| ((self.x / self.d) as i64, (self.y / self.d) as i64))
+    }
+}
+
+/// Points are in (x, y) order, which is the order the sweep visits them.
+impl Ord for Point {
+    fn cmp(&self, other: &Point) -> Ordering {
+        let x = wide_mul(self.x, other.d).cmp(&wide_mul(other.x, self.d));
+        x.then_with(|| wide_mul(self.y, other.d).cmp(&wide_mul(other.y, self.d)))
+    }
+}
+
+impl PartialOrd for Point {
+    fn partial_cmp(&self, other: &Point) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Point {
+    fn eq(&self, other: &Point) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Point {}
+
+/// A 256-bit signed integer in two's complement, which is just enough to
+/// compare sums of products of the `i128`s in the sweep exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Wide {
+    hi: i128,
+    lo: u128,
+}
+
+impl Wide {
+    fn add(self, other: Wide) -> Wide {
+        let (lo, carry) = self.lo.overflowing_add(other.lo);
+        Wide {
+            hi: self.hi.wrapping_add(other.hi).wrapping_add(carry as i128),
+            lo,
+        }
+    }
+
+    fn neg(self) -> Wide {
+        let lo = (!self.lo).wrapping_add(1);
+        Wide {
+            hi: (!self.hi).wrapping_add((lo == 0) as i128),
+            lo,
+        }
+    }
+}
+
+fn wide_mul(a: i128, b: i128) -> Wide {
+    const LOW: u128 = u64::MAX as u128;
+    let (ua, ub) = (a.unsigned_abs(), b.unsigned_abs());
+    let (a_hi, a_lo, b_hi, b_lo) = (ua >> 64, ua & LOW, ub >> 64, ub & LOW);
+
+    let low = a_lo * b_lo;
+    let (cross1, cross2) = (a_hi * b_lo, a_lo * b_hi);
+    let mid = (low >> 64) + (cross1 & LOW) + (cross2 & LOW);
+    let product = Wide {
+        hi: (a_hi * b_hi + (cross1 >> 64) + (cross2 >> 64) + (mid >> 64)) as i128,
+        lo: (low & LOW) | (mid << 64),
+    };
+
+    if (a < 0) != (b < 0) {
+        product.neg()
+    } else {
+        product
+    }
+}
+
+impl Piece {
+    fn is_point(&self) -> bool {
+        self.a == self.b
+    }
+
+    fn is_vertical(&self) -> bool {
+        self.a.0 == self.b.0
+    }
+
+    fn along(&self) -> (i64, i64) {
+        (self.b.0 - self.a.0, self.b.1 - self.a.1)
+    }
+
+    /// Whether the piece crosses the vertical line through `p` below,
+    /// at, or above `p`. A vertical piece is only ever asked about points
+    /// on it, so it's taken to cross at `p` itself.
+    fn cmp_at(&self, p: &Point) -> Ordering {
+        if self.is_vertical() {
+            return Ordering::Equal;
+        }
+
+        // Compare a.y + (p.x / p.d - a.x) * dy / dx with p.y / p.d, with
+        // both sides multiplied by p.d * dx, which is positive.
+        let (dx, dy) = self.along();
+        let (a_x, a_y) = (self.a.0 as i128, self.a.1 as i128);
+        let piece = wide_mul(a_y * dx as i128, p.d).add(wide_mul(p.x - a_x * p.d, dy as i128));
+        piece.cmp(&wide_mul(p.y, dx as i128))
+    }
+
+    /// Orders pieces leaving the same point by which is lower just after
+    /// it. Vertical pieces go up, so they're highest.
+    fn cmp_slope(&self, other: &Piece) -> Ordering {
+        let ((dx1, dy1), (dx2, dy2)) = (self.along(), other.along());
+        match (self.is_vertical(), other.is_vertical()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) => (dy1 as i128 * dx2 as i128).cmp(&(dy2 as i128 * dx1 as i128)),
+        }
+    }
+
+    /// The point where two pieces on different lines cross, if they do.
+    fn crossing(&self, other: &Piece) -> Option<Point> {
+        let (d1, d2) = (self.along(), other.along());
+        let denom = cross(d1, d2);
+        if denom == 0 {
+            return None;
+        }
+
+        // Solve a + t * d1 = c + u * d2, with t = t_num / denom and
+        // u = u_num / denom, both of which must be within 0..=1.
+        let (a, c) = (self.a, other.a);
+        let offset = (c.0 - a.0, c.1 - a.1);
+        let (t_num, u_num) = (cross(offset, d2), cross(offset, d1));
+        let within = |n: i128| {
+            if denom > 0 {
+                0 <= n && n <= denom
+            } else {
+                denom <= n && n <= 0
+            }
+        };
+        if !within(t_num) || !within(u_num) {
+            return None;
+        }
+
+        let sign = denom.signum();
+        let coord = |start: i64, step: i64| sign * (start as i128 * denom + t_num * step as i128);
+        Some(Point {
+            x: coord(a.0, d1.0),
+            y: coord(a.1, d1.1),
+            d: denom.abs(),
+        })
+    }
+}
+
+/// Sweeps a vertical line across the pieces from left to right, calling
+/// `meet` with each lattice point where two or more pieces meet and the
+/// pieces meeting there.
+///
+/// Events are the ends of pieces and the points where pieces cross, in
+/// (x, y) order. The pieces crossing the sweep line are kept from bottom
+/// to top, so the ones passing through an event are all together; they're
+/// cut out and the ones carrying on are put back in their new order. Only
+/// the pieces next to that run can newly become neighbors, so they're the
+/// only ones checked for crossings further on.
+fn sweep<F>(pieces: &[Piece], mut meet: F)
+where
+    F: FnMut((i64, i64), &[usize]),
+{
+    let mut events: BTreeMap<Point, Vec<usize>> = BTreeMap::new();
+    for (idx, piece) in pieces.iter().enumerate() {
+        events.entry(Point::new(piece.a)).or_default().push(idx);
+        events.entry(Point::new(piece.b)).or_default();
+    }
+
+    let mut status = Status::default();
+    let mut root = None;
+
+    while let Some((p, starting)) = events.pop_first() {
+        let lattice = p.lattice();
+        let (below, rest) = status.split(root, &|idx| pieces[idx].cmp_at(&p) == Ordering::Less);
+        let (through, above) =
+            status.split(rest, &|idx| pieces[idx].cmp_at(&p) != Ordering::Greater);
+
+        let mut passing = vec![];
+        status.collect(through, &mut passing);
+
+        let mut meeting = starting.clone();
+        meeting.extend(&passing);
+        if let (Some(point), true) = (lattice, meeting.len() > 1) {
+            meet(point, &meeting);
+        }
+
+        let mut continuing: Vec<usize> = starting
+            .into_iter()
+            .filter(|&idx| !pieces[idx].is_point())
+            .chain(
+                passing
+                    .into_iter()
+                    .filter(|&idx| Point::new(pieces[idx].b) != p),
+            )
+            .collect();
+        continuing.sort_by(|&i, &j| pieces[i].cmp_slope(&pieces[j]));
+
+        let mut check = |lower: Option<usize>, upper: Option<usize>| {
+            if let (Some(lower), Some(upper)) = (lower, upper) {
+                if let Some(q) = pieces[lower].crossing(&pieces[upper]) {
+                    if q > p {
+                        events.entry(q).or_default();
+                    }
+                }
+            }
+        };
+        match (continuing.first(), continuing.last()) {
+            (Some(&first), Some(&last)) => {
+                check(status.last(below), Some(first));
+                check(Some(last), status.first(above));
+            }
+            _ => check(status.last(below), status.first(above)),
+        }
+
+        let mut middle = None;
+        for idx in continuing {
+            let node = status.node(idx);
+            middle = status.merge(middle, Some(node));
+        }
+        let lower = status.merge(below, middle);
+        root = status.merge(lower, above);
+    }
+}
+
+/// The pieces crossing the sweep line, in order, as a treap: a binary tree
+/// in order by position and a heap by random priority, which keeps it
+/// balanced whatever order pieces are added in. Splitting off a run of
+/// pieces and merging trees back together both take O(log n).
+#[derive(Default)]
+struct Status {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    piece: usize,
+    priority: u64,
+    left: Option<usize>,
+    right: Option<usize>,
+}
+
+impl Status {
+    fn node(&mut self, piece: usize) -> usize {
+        // splitmix64 of the node number, for a well-spread priority.
+        let mut z = (self.nodes.len() as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        self.nodes.push(Node {
+            piece,
+            priority: z ^ (z >> 31),
+            left: None,
+            right: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Splits a tree into the leading pieces for which `goes_left` holds,
+    /// and the rest. `goes_left` must hold for a prefix of the pieces.
+    fn split(
+        &mut self,
+        root: Option<usize>,
+        goes_left: &dyn Fn(usize) -> bool,
+    ) -> (Option<usize>, Option<usize>) {
+        let node = match root {
+            Some(node) => node,
+            None => return (None, None),
+        };
+
+        if goes_left(self.nodes[node].piece) {
+            let (left, right) = self.split(self.nodes[node].right, goes_left);
+            self.nodes[node].right = left;
+            (Some(node), right)
+        } else {
+            let (left, right) = self.split(self.nodes[node].left, goes_left);
+            self.nodes[node].left = right;
+            (left, Some(node))
+        }
+    }
+
+    /// Joins two trees, with every piece in `left` before every piece in
+    /// `right`.
+    fn merge(&mut self, left: Option<usize>, right: Option<usize>) -> Option<usize> {
+        match (left, right) {
+            (None, tree) | (tree, None) => tree,
+            (Some(l), Some(r)) => {
+                if self.nodes[l].priority > self.nodes[r].priority {
+                    self.nodes[l].right = self.merge(self.nodes[l].right, right);
+                    Some(l)
+                } else {
+                    self.nodes[r].left = self.merge(left, self.nodes[r].left);
+                    Some(r)
+                }
+            }
+        }
+    }
+
+    fn first(&self, mut root: Option<usize>) -> Option<usize> {
+        let mut piece = None;
+        while let Some(node) = root {
+            piece = Some(self.nodes[node].piece);
+            root = self.nodes[node].left;
+        }
+        piece
+    }
+
+    fn last(&self, mut root: Option<usize>) -> Option<usize> {
+        let mut piece = None;
+        while let Some(node) = root {
+            piece = Some(self.nodes[node].piece);
+            root = self.nodes[node].right;
+        }
+        piece
+    }
+
+    fn collect(&self, root: Option<usize>, out: &mut Vec<usize>) {
+        if let Some(node) = root {
+            self.collect(self.nodes[node].left, out);
+            out.push(self.nodes[node].piece);
+            self.collect(self.nodes[node].right, out);
+        }
+    }
+}
+
+/// Identifies the line a segment lies on: the smallest integer step along
+/// it, `(p, q)`, and the value of `q * x - p * y`, which is the same for
+/// every point on the line. These don't fit in an `i64` for lines across
+/// the whole range of `i32` coordinates, so they're kept as `i128`s, along
+/// with positions on the line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct LineKey {
+    p: i128,
+    q: i128,
+    c: i128,
+}
+
+impl LineKey {
+    /// Where a point on the line is along it. Neighboring lattice points on
+    /// the line are `spacing()` apart.
+    fn position(&self, (x, y): (i64, i64)) -> i128 {
+        self.p * x as i128 + self.q * y as i128
+    }
+
+    fn spacing(&self) -> i128 {
+        self.p * self.p + self.q * self.q
+    }
+
+    /// The point on the line at a position, the inverse of `position`.
+    fn point(&self, t: i128) -> (i64, i64) {
+        let (p, q, c) = (self.p, self.q, self.c);
+        (
+            ((p * t + q * c) / self.spacing()) as i64,
+            ((q * t - p * c) / self.spacing()) as i64,
+        )
+    }
+}
+
+/// Merges closed ranges and returns the parts covered by at least two.
+fn covered_twice(ranges: Vec<(i128, i128)>) -> Vec<(i128, i128)> {
+    // Starts sort before ends at the same position, so ranges which only
+    // touch still count as overlapping there.
+    let mut events: Vec<(i128, i32)> = ranges
+        .iter()
+        .flat_map(|&(lo, hi)| [(lo, -1), (hi, 1)])
+        .collect();
+    events.sort_unstable();
+
+    let mut result = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (t, kind) in events {
+        if kind < 0 {
+            depth += 1;
+            if depth == 2 {
+                start = t;
+            }
+        } else {
+            if depth == 2 {
+                result.push((start, t));
+            }
+            depth -= 1;
+        }
+    }
+
+    result
+}
+
+/// Merges closed ranges which overlap or touch.
+fn merge_ranges(ranges: &[(i128, i128)]) -> Vec<(i128, i128)> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+
+    let mut merged: Vec<(i128, i128)> = vec![];
+    for (lo, hi) in sorted {
+        match merged.last_mut() {
+            Some(last) if lo <= last.1 => last.1 = last.1.max(hi),
+            _ => merged.push((lo, hi)),
+        }
+    }
+    merged
+}
+
+fn cross(a: (i64, i64), b: (i64, i64)) -> i128 {
+    a.0 as i128 * b.1 as i128 - a.1 as i128 * b.0 as i128
+}
+
+/// How to turn a segment into the points it covers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Raster {
+    /// Only the points exactly on the segment.
+    Exact,
+    /// A connected line of the points nearest to the segment.
+    Bresenham,
+}
+
 fn parse_tuple(input: &str) -> (i32, i32) {
     // No error handling because we're just covering the provided input.
     // In practice, let's not assume that everything works out.
@@ -111,6 +845,8 @@ fn puzzle_input() -> &'static str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::XorShift;
+    use num::BigInt;
 
     fn example_input() -> &'static str {
         include_str!("../inputs/day05_example.txt")
@@ -134,4 +870,216 @@ mod test {
     fn verify_example_input_part2() {
         assert_eq!(12, solve_part2(example_input()));
     }
+
+    #[test]
+    fn rasterizes_any_slope() {
+        let shallow = LineSegment::parse("0,0 -> 6,4");
+        assert_eq!(vec![(0, 0), (3, 2), (6, 4)], shallow.points_covered());
+
+        let diagonal = LineSegment::parse("5,5 -> 8,2");
+        assert_eq!(
+            vec![(5, 5), (6, 4), (7, 3), (8, 2)],
+            diagonal.points_covered()
+        );
+        assert_eq!(
+            vec![(5, 5), (6, 4), (7, 3), (8, 2)],
+            diagonal.rasterize(Raster::Bresenham)
+        );
+
+        let point = LineSegment::parse("3,3 -> 3,3");
+        assert_eq!(vec![(3, 3)], point.points_covered());
+        assert_eq!(vec![(3, 3)], point.rasterize(Raster::Bresenham));
+
+        let steep = LineSegment::parse("5,2 -> 0,0");
+        assert_eq!(vec![(5, 2), (0, 0)], steep.points_covered());
+        assert_eq!(
+            vec![(0, 0), (1, 0), (2, 1), (3, 1), (4, 2), (5, 2)],
+            steep.rasterize(Raster::Bresenham)
+        );
+    }
+
+    #[test]
+    fn rasterizes_segments_spanning_the_whole_coordinate_range() {
+        let segment = LineSegment::parse("-2000000000,0 -> 2000000000,2");
+        assert_eq!(
+            vec![(-2000000000, 0), (0, 1), (2000000000, 2)],
+            segment.points_covered()
+        );
+
+        let segment = LineSegment::parse("2147483647,-2147483648 -> 2147483645,-2147483647");
+        let points = segment.rasterize(Raster::Bresenham);
+        assert_eq!(3, points.len());
+        assert_eq!(Some(&(2147483645, -2147483647)), points.first());
+        assert_eq!(Some(&(2147483647, -2147483648)), points.last());
+    }
+
+    #[test]
+    fn counts_overlaps_on_arbitrary_slopes() {
+        let line_segments = parse_input("0,0 -> 6,4\n3,0 -> 3,5\n0,4 -> 6,0");
+        let map = VentMap::from_segments(&line_segments, LineSegment::points_covered);
+        assert_eq!(1, map.covered_at_least(2));
+        assert_eq!(3, map.count((3, 2)));
+
+        let map = VentMap::from_segments(&line_segments, |s| s.rasterize(Raster::Bresenham));
+        assert_eq!(3, map.count((3, 2)));
+    }
+
+    #[test]
+    fn counts_overlaps_analytically() {
+        let line_segments = parse_input(example_input());
+        let hv: Vec<_> = line_segments
+            .iter()
+            .filter(|s| s.a.0 == s.b.0 || s.a.1 == s.b.1)
+            .copied()
+            .collect();
+        assert_eq!(5, count_overlaps(&hv));
+        assert_eq!(12, count_overlaps(&line_segments));
+
+        let line_segments = parse_input(puzzle_input());
+        let map = VentMap::from_segments(&line_segments, LineSegment::points_covered);
+        assert_eq!(map.covered_at_least(2), count_overlaps(&line_segments));
+    }
+
+    #[test]
+    fn counts_overlaps_with_huge_coordinates() {
+        let line_segments = parse_input(
+            "0,0 -> 2000000,2000000
+0,2000000 -> 2000000,0
+0,0 -> 3000000,0
+4000000,0 -> 1000000,0
+1500000,-5 -> 1500000,5
+0,1 -> 3,2
+1500000,0 -> 1500000,0",
+        );
+        // The shared stretch of the two horizontal lines, plus where the
+        // diagonals cross each other and where the first one starts on the
+        // horizontal line. The vertical line, the single point, and the end
+        // of the second diagonal only touch points which are already in the
+        // shared stretch, and the shallow line doesn't cross the diagonal at
+        // a lattice point.
+        assert_eq!(2000001 + 2, count_overlaps(&line_segments));
+    }
+
+    #[test]
+    fn counts_overlaps_across_the_whole_coordinate_range() {
+        let line_segments = parse_input(
+            "-2147483648,0 -> 2147483647,1
+2147483647,1 -> -2147483648,0
+-2147483648,-2147483648 -> 2147483647,2147483647
+0,0 -> 2147483647,2147483647
+-2147483648,5 -> 2147483647,5
+2147483647,5 -> -2147483648,5",
+        );
+        // The shallow lines only meet the lattice at their ends, which
+        // they share. The diagonals share 0,0 and everything after it, and
+        // the horizontal lines share all of theirs, including 5,5 which is
+        // also on the diagonals. The shallow lines cross the others between
+        // lattice points.
+        assert_eq!(
+            2 + (1 << 31) + (1 << 32) - 1,
+            count_overlaps(&line_segments)
+        );
+    }
+
+    #[test]
+    fn analytic_overlaps_match_rasterized_overlaps() {
+        let mut rng = XorShift::new(0x853c_49e6_748f_ea9b);
+        let mut coord = || (rng.next_u64() % 9) as i32 - 4;
+
+        for _ in 0..200 {
+            let line_segments: Vec<_> = (0..30)
+                .map(|_| LineSegment {
+                    a: (coord(), coord()),
+                    b: (coord(), coord()),
+                })
+                .collect();
+            let map = VentMap::from_segments(&line_segments, LineSegment::points_covered);
+            assert_eq!(
+                map.covered_at_least(2),
+                count_overlaps(&line_segments),
+                "{:?}",
+                line_segments
+            );
+        }
+    }
+
+    #[test]
+    fn sweeps_many_parallel_lines() {
+        // Every pair of these overlaps in x, so pruning by bounding box
+        // alone would compare all of them, but none ever meet.
+        let line_segments: Vec<_> = (0..20_000)
+            .map(|i| LineSegment {
+                a: (0, i),
+                b: (1_000_000, 1_000_000 + i),
+            })
+            .collect();
+        assert_eq!(0, count_overlaps(&line_segments));
+    }
+
+    #[test]
+    fn wide_products_are_exact() {
+        let values = [
+            0,
+            1,
+            -1,
+            7,
+            -13,
+            u64::MAX as i128,
+            -(u64::MAX as i128),
+            1 << 100,
+            -(1 << 100) + 12345,
+            i128::MAX / 3,
+        ];
+        let to_big = |w: Wide| (BigInt::from(w.hi) << 128) + BigInt::from(w.lo);
+
+        for &a in &values {
+            for &b in &values {
+                let product = wide_mul(a, b);
+                assert_eq!(
+                    BigInt::from(a) * BigInt::from(b),
+                    to_big(product),
+                    "{a} * {b}"
+                );
+                assert_eq!(-(BigInt::from(a) * BigInt::from(b)), to_big(product.neg()));
+                for &c in &values {
+                    let sum = product.add(wide_mul(c, 3));
+                    assert_eq!(BigInt::from(a) * b + BigInt::from(c) * 3, to_big(sum));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn renders_example_diagram() {
+        let line_segments = parse_input(example_input());
+        let map = VentMap::from_segments(&line_segments, LineSegment::points_covered);
+
+        let expected = "\
+1.1....11.
+.111...2..
+..2.1.111.
+...1.2.2..
+.112313211
+...1.2....
+..1...1...
+.1.....1..
+1.......1.
+222111....
+";
+        assert!(map.is_dense());
+        assert_eq!(expected, map.render());
+        assert_eq!(2, map.count((7, 1)));
+        assert_eq!(39, map.covered_at_least(1));
+    }
+
+    #[test]
+    fn uses_sparse_storage_for_spread_out_lines() {
+        let line_segments = parse_input("0,0 -> 0,3\n1000000,0 -> 1000000,3\n0,2 -> 0,5");
+        let map = VentMap::from_segments(&line_segments, LineSegment::points_covered);
+
+        assert!(!map.is_dense());
+        assert_eq!(2, map.covered_at_least(2));
+        assert_eq!(10, map.covered_at_least(1));
+        assert_eq!(1, map.count((1000000, 3)));
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use num::{BigUint, One, Zero};
 
 pub fn run() {
     let input = puzzle_input();
@@ -6,14 +6,14 @@ pub fn run() {
     println!("day06.part2.solution = {}", solve_part2(input));
 }
 
-fn solve_part1(input: &str) -> usize {
+fn solve_part1(input: &str) -> BigUint {
     let initial_fish = parse_input(input);
-    simulate(initial_fish, 80)
+    simulate(&initial_fish, 80)
 }
 
-fn solve_part2(input: &str) -> usize {
+fn solve_part2(input: &str) -> BigUint {
     let initial_fish = parse_input(input);
-    simulate(initial_fish, 256)
+    simulate(&initial_fish, 256)
 }
 
 fn parse_input(input: &str) -> Vec<u8> {
@@ -24,33 +24,304 @@ fn parse_input(input: &str) -> Vec<u8> {
         .collect()
 }
 
-fn simulate(initial_fish: Vec<u8>, days: usize) -> usize {
-    let mut cycle_counts: HashMap<u8, usize> = HashMap::new();
+/// The rules the fish live by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lifecycle {
+    /// A fish's timer goes back to this after it spawns.
+    pub reset_timer: usize,
+    /// Newborn fish start with this timer.
+    pub newborn_timer: usize,
+    /// How many fish are born each time a fish spawns.
+    pub offspring: usize,
+    /// If set, fish die once they have lived this many days. Fish alive at
+    /// the start are treated as being born that day.
+    pub max_lifespan: Option<usize>,
+}
+
+impl Lifecycle {
+    /// The lanternfish from the puzzle.
+    pub const LANTERNFISH: Lifecycle = Lifecycle {
+        reset_timer: 6,
+        newborn_timer: 8,
+        offspring: 1,
+        max_lifespan: None,
+    };
+
+    /// Counts the fish after `days` days exactly. Without a maximum lifespan
+    /// this takes time logarithmic in `days`; with one, it's linear.
+    pub fn simulate(&self, initial_fish: &[u8], days: u64) -> BigUint {
+        self.count_fish(&Exact, initial_fish, days)
+    }
+
+    /// Counts the fish after `days` days, modulo `modulus`.
+    pub fn simulate_mod(&self, initial_fish: &[u8], days: u64, modulus: u64) -> u64 {
+        assert!(modulus > 0, "Modulus must be positive");
+        self.count_fish(&Modulo(modulus), initial_fish, days)
+    }
+
+    /// The number of fish at the start and at the end of each day, so the
+    /// result has `days + 1` entries.
+    ///
+    /// ```
+    /// use aoc::day06::Lifecycle;
+    /// let mayfly = Lifecycle {
+    ///     max_lifespan: Some(3),
+    ///     ..Lifecycle::LANTERNFISH
+    /// };
+    /// let counts: Vec<u32> = mayfly
+    ///     .population(&[1], 4)
+    ///     .iter()
+    ///     .map(|n| n.try_into().unwrap())
+    ///     .collect();
+    /// assert_eq!(counts, vec![1, 1, 2, 1, 1]);
+    /// ```
+    pub fn population(&self, initial_fish: &[u8], days: u64) -> Vec<BigUint> {
+        let mut counts = self.initial_counts(&Exact, initial_fish);
+        let mut series = vec![total(&Exact, &counts)];
+        let offspring = self.offspring_count(&Exact);
+
+        for _ in 0..days {
+            counts = self.step(&Exact, &offspring, &counts);
+            series.push(total(&Exact, &counts));
+        }
+
+        series
+    }
+
+    fn timers(&self) -> usize {
+        self.reset_timer.max(self.newborn_timer) + 1
+    }
+
+    /// How many ages we need to keep track of: just one (any age) if fish
+    /// never die.
+    fn ages(&self) -> usize {
+        self.max_lifespan.unwrap_or(1)
+    }
+
+    /// The count of fish of each age with each timer value.
+    fn initial_counts<A: Arithmetic>(&self, arith: &A, initial_fish: &[u8]) -> Vec<A::Value> {
+        assert!(self.ages() > 0, "Fish must live for at least a day");
+
+        let mut counts = vec![arith.zero(); self.ages() * self.timers()];
+        for &fish in initial_fish {
+            let timer = fish as usize;
+            assert!(timer < self.timers(), "Timer {} is out of range", timer);
+            counts[timer] = arith.add(&counts[timer], &arith.one());
+        }
+        counts
+    }
+
+    /// How many fish each fish spawns at once, as a value for `step`.
+    fn offspring_count<A: Arithmetic>(&self, arith: &A) -> A::Value {
+        arith.value_of(self.offspring as u64)
+    }
+
+    /// Advances the counts from `initial_counts` by one day, with each
+    /// spawning fish having `offspring` (from `offspring_count`) young.
+    fn step<A: Arithmetic>(
+        &self,
+        arith: &A,
+        offspring: &A::Value,
+        counts: &[A::Value],
+    ) -> Vec<A::Value> {
+        let timers = self.timers();
+        let ages = self.ages();
+        let aging = self.max_lifespan.is_some() as usize;
 
-    for cycle in 0..=8 {
-        cycle_counts.insert(cycle, 0);
+        let mut next = vec![arith.zero(); counts.len()];
+        let mut add = |age: usize, timer: usize, n: &A::Value| {
+            if age < ages {
+                let idx = age * timers + timer;
+                next[idx] = arith.add(&next[idx], n);
+            }
+        };
+
+        for age in 0..ages {
+            for timer in 0..timers {
+                let n = &counts[age * timers + timer];
+                if timer > 0 {
+                    add(age + aging, timer - 1, n);
+                } else {
+                    add(age + aging, self.reset_timer, n);
+                    add(0, self.newborn_timer, &arith.mul(n, offspring));
+                }
+            }
+        }
+
+        next
     }
 
-    for &fish in &initial_fish {
-        cycle_counts.entry(fish).and_modify(|e| *e += 1);
+    /// The matrix which takes today's counts to tomorrow's:
+    /// `next[i] = sum(m[i][j] * current[j])`.
+    fn transition_matrix<A: Arithmetic>(&self, arith: &A) -> Matrix<A::Value> {
+        let states = self.ages() * self.timers();
+        let offspring = self.offspring_count(arith);
+        let mut columns = vec![];
+
+        for j in 0..states {
+            let mut unit = vec![arith.zero(); states];
+            unit[j] = arith.one();
+            columns.push(self.step(arith, &offspring, &unit));
+        }
+
+        (0..states)
+            .map(|i| columns.iter().map(|col| col[i].clone()).collect())
+            .collect()
     }
 
-    for _ in 0..days {
-        let mut next_counts = cycle_counts.clone();
+    fn count_fish<A: Arithmetic>(&self, arith: &A, initial_fish: &[u8], days: u64) -> A::Value {
+        let mut counts = self.initial_counts(arith, initial_fish);
 
-        for cycle in (1..=8).rev() {
-            next_counts
-                .entry(cycle - 1)
-                .and_modify(|e| *e = cycle_counts[&cycle]);
+        if self.max_lifespan.is_some() {
+            let offspring = self.offspring_count(arith);
+            for _ in 0..days {
+                counts = self.step(arith, &offspring, &counts);
+            }
+            return total(arith, &counts);
         }
 
-        next_counts.entry(8).and_modify(|e| *e = cycle_counts[&0]);
-        next_counts.entry(6).and_modify(|e| *e += cycle_counts[&0]);
+        // Raise the transition matrix to the power `days` by repeated
+        // squaring, then apply it to the counts.
+        let mut power = self.transition_matrix(arith);
+        let mut result = identity(arith, counts.len());
+        let mut remaining = days;
+        while remaining > 0 {
+            if remaining & 1 == 1 {
+                result = mat_mul(arith, &result, &power);
+            }
+            power = mat_mul(arith, &power, &power);
+            remaining >>= 1;
+        }
 
-        cycle_counts = next_counts;
+        let mut sum = arith.zero();
+        for row in &result {
+            for (m, count) in row.iter().zip(counts.iter()) {
+                sum = arith.add(&sum, &arith.mul(m, count));
+            }
+        }
+        sum
     }
+}
+
+/// Counts the lanternfish after `days` days exactly. This takes time
+/// logarithmic in `days`, though the count itself grows exponentially.
+///
+/// ```
+/// use aoc::day06::simulate;
+/// assert_eq!(simulate(&[3, 4, 3, 1, 2], 18), 26u32.into());
+/// ```
+pub fn simulate(initial_fish: &[u8], days: u64) -> BigUint {
+    Lifecycle::LANTERNFISH.simulate(initial_fish, days)
+}
 
-    cycle_counts.values().sum()
+/// Counts the lanternfish after `days` days, modulo `modulus`.
+///
+/// ```
+/// use aoc::day06::simulate_mod;
+/// assert_eq!(simulate_mod(&[3, 4, 3, 1, 2], 18, 10), 6);
+/// ```
+pub fn simulate_mod(initial_fish: &[u8], days: u64, modulus: u64) -> u64 {
+    Lifecycle::LANTERNFISH.simulate_mod(initial_fish, days, modulus)
+}
+
+/// The numbers fish are counted with.
+trait Arithmetic {
+    type Value: Clone;
+
+    fn zero(&self) -> Self::Value;
+    fn one(&self) -> Self::Value;
+    fn value_of(&self, n: u64) -> Self::Value;
+    fn add(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+    fn mul(&self, a: &Self::Value, b: &Self::Value) -> Self::Value;
+}
+
+struct Exact;
+
+impl Arithmetic for Exact {
+    type Value = BigUint;
+
+    fn zero(&self) -> BigUint {
+        BigUint::zero()
+    }
+
+    fn one(&self) -> BigUint {
+        BigUint::one()
+    }
+
+    fn value_of(&self, n: u64) -> BigUint {
+        BigUint::from(n)
+    }
+
+    fn add(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a + b
+    }
+
+    fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        a * b
+    }
+}
+
+struct Modulo(u64);
+
+impl Arithmetic for Modulo {
+    type Value = u64;
+
+    fn zero(&self) -> u64 {
+        0
+    }
+
+    fn one(&self) -> u64 {
+        1 % self.0
+    }
+
+    fn value_of(&self, n: u64) -> u64 {
+        n % self.0
+    }
+
+    fn add(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 + *b as u128) % self.0 as u128) as u64
+    }
+
+    fn mul(&self, a: &u64, b: &u64) -> u64 {
+        ((*a as u128 * *b as u128) % self.0 as u128) as u64
+    }
+}
+
+type Matrix<T> = Vec<Vec<T>>;
+
+fn mat_mul<A: Arithmetic>(
+    arith: &A,
+    a: &Matrix<A::Value>,
+    b: &Matrix<A::Value>,
+) -> Matrix<A::Value> {
+    let n = a.len();
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| {
+                    (0..n).fold(arith.zero(), |acc, k| {
+                        arith.add(&acc, &arith.mul(&a[i][k], &b[k][j]))
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn identity<A: Arithmetic>(arith: &A, n: usize) -> Matrix<A::Value> {
+    (0..n)
+        .map(|i| {
+            (0..n)
+                .map(|j| if i == j { arith.one() } else { arith.zero() })
+                .collect()
+        })
+        .collect()
+}
+
+fn total<A: Arithmetic>(arith: &A, counts: &[A::Value]) -> A::Value {
+    counts
+        .iter()
+        .fold(arith.zero(), |acc, n| arith.add(&acc, n))
 }
 
 fn puzzle_input() -> &'static str {
@@ -74,17 +345,90 @@ mod test {
     #[test]
     fn verify_small_simulation() {
         let fish: Vec<u8> = vec![3, 4, 3, 1, 2];
-        assert_eq!(7, simulate(fish.clone(), 3));
-        assert_eq!(26, simulate(fish.clone(), 18));
+        assert_eq!(BigUint::from(5u32), simulate(&fish, 0));
+        assert_eq!(BigUint::from(7u32), simulate(&fish, 3));
+        assert_eq!(BigUint::from(26u32), simulate(&fish, 18));
     }
 
     #[test]
     fn verify_example_input_part1() {
-        assert_eq!(5934, solve_part1(example_input()));
+        assert_eq!(BigUint::from(5934u32), solve_part1(example_input()));
     }
 
     #[test]
     fn verify_example_input_part2() {
-        assert_eq!(26984457539, solve_part2(example_input()));
+        assert_eq!(BigUint::from(26984457539u64), solve_part2(example_input()));
+    }
+
+    #[test]
+    fn simulates_huge_horizons() {
+        let fish = parse_input(example_input());
+        let modulus = 1_000_000_007;
+
+        assert_eq!(26984457539 % modulus, simulate_mod(&fish, 256, modulus));
+        assert_eq!(
+            simulate(&fish, 10_000) % BigUint::from(modulus),
+            BigUint::from(simulate_mod(&fish, 10_000, modulus))
+        );
+
+        // Just needs to finish quickly.
+        simulate_mod(&fish, 5_000_000_000, modulus);
+        assert_eq!(0, simulate_mod(&fish, 5_000_000_000, 1));
+    }
+
+    #[test]
+    fn matches_population_series() {
+        let fish = parse_input(example_input());
+        let series = Lifecycle::LANTERNFISH.population(&fish, 80);
+
+        assert_eq!(81, series.len());
+        assert_eq!(BigUint::from(5u32), series[0]);
+        assert_eq!(BigUint::from(26u32), series[18]);
+        assert_eq!(BigUint::from(5934u32), series[80]);
+    }
+
+    #[test]
+    fn supports_other_lifecycles() {
+        let fish = vec![0];
+        let twins = Lifecycle {
+            reset_timer: 2,
+            newborn_timer: 2,
+            offspring: 2,
+            max_lifespan: None,
+        };
+        // Every fish spawns every third day, so the population triples.
+        assert_eq!(BigUint::from(27u32), twins.simulate(&fish, 7));
+        assert_eq!(twins.population(&fish, 40)[40], twins.simulate(&fish, 40));
+
+        let short_lived = Lifecycle {
+            max_lifespan: Some(10),
+            ..Lifecycle::LANTERNFISH
+        };
+        let series = short_lived.population(&fish, 30);
+        assert_eq!(series[30], short_lived.simulate(&fish, 30));
+        assert_eq!(
+            series[30].clone() % BigUint::from(7u32),
+            BigUint::from(short_lived.simulate_mod(&fish, 30, 7))
+        );
+        // The first fish spawns on days 1 and 8, and dies on day 10 just as
+        // its first child spawns.
+        assert_eq!(BigUint::from(3u32), series[10]);
+        assert_eq!(BigUint::from(2u32), series[11]);
+    }
+
+    #[test]
+    fn supports_huge_litters() {
+        // Just needs to finish quickly, which it wouldn't if the litter size
+        // were counted up one at a time.
+        let swarm = Lifecycle {
+            offspring: 1 << 40,
+            ..Lifecycle::LANTERNFISH
+        };
+        assert_eq!(BigUint::from((1u64 << 40) + 1), swarm.simulate(&[0], 1));
+        assert_eq!(((1 << 40) + 1) % 1000, swarm.simulate_mod(&[0], 1, 1000));
+        assert_eq!(
+            BigUint::from((1u64 << 40) + 1),
+            swarm.population(&[0], 1)[1]
+        );
     }
 }
@@ -4,33 +4,197 @@ pub fn run() {
     println!("day07.part2.solution = {}", solve_part2(input));
 }
 
-fn solve_part1(input: &str) -> i32 {
+fn solve_part1(input: &str) -> i64 {
     let positions = parse_input(input);
 
-    (0..*(positions.iter().max().unwrap()))
-        .map(|p| positions.iter().map(|x| (x - p).abs()).sum())
-        .min()
-        .unwrap()
+    align_median(&positions).fuel
 }
 
-fn solve_part2(input: &str) -> i32 {
+fn solve_part2(input: &str) -> i64 {
     let positions = parse_input(input);
 
-    (0..*(positions.iter().max().unwrap()))
-        .map(|p| positions.iter().map(|x| cost((x - p).abs())).sum())
-        .min()
-        .unwrap()
+    align(&positions, &Triangular).fuel
 }
 
-fn cost(steps: i32) -> i32 {
+/// How much fuel crabs burn to move. Any function from steps to fuel is a
+/// model where every crab is the same.
+pub trait FuelModel {
+    /// The fuel crab number `crab` burns to move `steps` steps.
+    fn fuel(&self, crab: usize, steps: i64) -> i64;
+}
+
+impl<F: Fn(i64) -> i64> FuelModel for F {
+    fn fuel(&self, _crab: usize, steps: i64) -> i64 {
+        self(steps)
+    }
+}
+
+/// Each step costs 1, as in part 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Linear;
+
+impl FuelModel for Linear {
+    fn fuel(&self, _crab: usize, steps: i64) -> i64 {
+        steps
+    }
+}
+
+/// Each step costs 1 more than the last, as in part 2.
+#[derive(Clone, Copy, Debug)]
+pub struct Triangular;
+
+impl FuelModel for Triangular {
+    fn fuel(&self, _crab: usize, steps: i64) -> i64 {
+        cost(steps)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quadratic;
+
+impl FuelModel for Quadratic {
+    fn fuel(&self, _crab: usize, steps: i64) -> i64 {
+        steps * steps
+    }
+}
+
+/// Scales another model by a weight for each crab, for crabs which burn
+/// more (or less) fuel than others.
+#[derive(Clone, Debug)]
+pub struct Weighted<M> {
+    pub model: M,
+    pub weights: Vec<i64>,
+}
+
+impl<M: FuelModel> FuelModel for Weighted<M> {
+    fn fuel(&self, crab: usize, steps: i64) -> i64 {
+        self.weights[crab] * self.model.fuel(crab, steps)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Alignment {
+    pub position: i64,
+    pub fuel: i64,
+}
+
+/// When each step costs the same, the median is the cheapest place to line
+/// up: moving away from it moves further from at least half the crabs.
+pub fn align_median(positions: &[i64]) -> Alignment {
+    let mut sorted = positions.to_vec();
+    sorted.sort_unstable();
+    let position = sorted[(sorted.len() - 1) / 2];
+
+    Alignment {
+        position,
+        fuel: total_fuel(positions, position, &Linear),
+    }
+}
+
+/// Finds the cheapest place to line up for any model where each crab's fuel
+/// is convex in the steps taken and never decreases (like `Triangular`, whose
+/// best position is always near the mean).
+///
+/// The total fuel is then convex in the position too, so it's enough to
+/// binary search between the outermost crabs for where it stops falling.
+/// If several positions are equally cheap, this returns the lowest.
+///
+/// ```
+/// use aoc::day07::{align, Alignment};
+/// let crabs = [16, 1, 2, 0, 4, 2, 7, 1, 2, 14];
+/// assert_eq!(align(&crabs, &|steps| steps * steps), Alignment { position: 5, fuel: 291 });
+/// ```
+pub fn align<M>(positions: &[i64], model: &M) -> Alignment
+where
+    M: FuelModel + ?Sized,
+{
+    let fuel = |p| total_fuel(positions, p, model);
+
+    let mut lo = *positions.iter().min().unwrap();
+    let mut hi = *positions.iter().max().unwrap();
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if fuel(mid + 1) >= fuel(mid) {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Alignment {
+        position: lo,
+        fuel: fuel(lo),
+    }
+}
+
+fn total_fuel<M>(positions: &[i64], target: i64, model: &M) -> i64
+where
+    M: FuelModel + ?Sized,
+{
+    positions
+        .iter()
+        .enumerate()
+        .map(|(crab, x)| model.fuel(crab, (x - target).abs()))
+        .sum()
+}
+
+/// The total fuel for lining up at each position from the lowest crab to
+/// the highest.
+pub fn cost_curve<M>(positions: &[i64], model: &M) -> Vec<(i64, i64)>
+where
+    M: FuelModel + ?Sized,
+{
+    let min = *positions.iter().min().unwrap();
+    let max = *positions.iter().max().unwrap();
+
+    (min..=max)
+        .map(|p| (p, total_fuel(positions, p, model)))
+        .collect()
+}
+
+/// Exports the cost curves for several named models side by side as CSV,
+/// with one row per position.
+///
+/// ```
+/// use aoc::day07::{cost_curve_csv, FuelModel, Linear, Triangular};
+/// let models: [(&str, &dyn FuelModel); 2] = [("linear", &Linear), ("triangular", &Triangular)];
+/// let csv = cost_curve_csv(&[0, 2], &models);
+/// assert_eq!(csv, "position,linear,triangular\n0,2,3\n1,2,2\n2,2,3\n");
+/// ```
+pub fn cost_curve_csv(positions: &[i64], models: &[(&str, &dyn FuelModel)]) -> String {
+    let curves: Vec<_> = models
+        .iter()
+        .map(|(_, model)| cost_curve(positions, *model))
+        .collect();
+
+    let mut csv = String::from("position");
+    for (name, _) in models {
+        csv.push(',');
+        csv.push_str(name);
+    }
+    csv.push('\n');
+
+    let rows = curves.first().map_or(0, |curve| curve.len());
+    for row in 0..rows {
+        csv.push_str(&curves[0][row].0.to_string());
+        for curve in &curves {
+            csv.push_str(&format!(",{}", curve[row].1));
+        }
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn cost(steps: i64) -> i64 {
     (steps * (steps + 1)) / 2
 }
 
-fn parse_input(input: &str) -> Vec<i32> {
+fn parse_input(input: &str) -> Vec<i64> {
     input
         .trim()
         .split(',')
-        .map(|n| n.parse::<i32>().unwrap())
+        .map(|n| n.parse::<i64>().unwrap())
         .collect()
 }
 
@@ -63,4 +227,79 @@ mod test {
         assert_eq!(6, cost(3));
         assert_eq!(10, cost(4));
     }
+
+    #[test]
+    fn finds_best_positions() {
+        let positions = parse_input(example_input());
+        assert_eq!(
+            Alignment {
+                position: 2,
+                fuel: 37
+            },
+            align_median(&positions)
+        );
+        assert_eq!(
+            Alignment {
+                position: 5,
+                fuel: 168
+            },
+            align(&positions, &Triangular)
+        );
+    }
+
+    #[test]
+    fn matches_exhaustive_search() {
+        let positions = parse_input(puzzle_input());
+        let (min, max) = (
+            *positions.iter().min().unwrap(),
+            *positions.iter().max().unwrap(),
+        );
+        let weighted = Weighted {
+            model: Triangular,
+            weights: (0..positions.len() as i64).map(|i| i % 5 + 1).collect(),
+        };
+        let models: [&dyn FuelModel; 4] = [&Linear, &Triangular, &Quadratic, &weighted];
+
+        for model in models {
+            let best = (min..=max)
+                .map(|p| total_fuel(&positions, p, model))
+                .min()
+                .unwrap();
+            assert_eq!(best, align(&positions, model).fuel);
+        }
+        assert_eq!(
+            align(&positions, &Linear).fuel,
+            align_median(&positions).fuel
+        );
+    }
+
+    #[test]
+    fn considers_the_furthest_crab() {
+        // The old search stopped one short of the furthest crab, which is
+        // where these crabs should line up.
+        let positions = [0, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9];
+        assert_eq!(9, align_median(&positions).position);
+        assert_eq!(9, align(&positions, &Triangular).position);
+    }
+
+    #[test]
+    fn weights_crabs() {
+        // A heavy crab at 10 pulls everyone towards it.
+        let positions = [0, 1, 10];
+        let weighted = Weighted {
+            model: Linear,
+            weights: vec![1, 1, 5],
+        };
+        assert_eq!(
+            Alignment {
+                position: 10,
+                fuel: 19
+            },
+            align(&positions, &weighted)
+        );
+
+        let curve = cost_curve(&positions, &weighted);
+        assert_eq!(11, curve.len());
+        assert_eq!((0, 1 + 50), curve[0]);
+    }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::fmt;
 
 pub fn run() {
     let input = puzzle_input();
@@ -7,6 +7,7 @@ pub fn run() {
 }
 
 fn solve_part1(input: &str) -> usize {
+    let glyphs = GlyphSet::seven_segment();
     input
         .trim()
         .lines()
@@ -14,122 +15,342 @@ fn solve_part1(input: &str) -> usize {
             let (_, outputs) = line.split_once('|').unwrap();
             outputs
                 .split_ascii_whitespace()
-                .filter(|&r| is_easy_digit(r))
+                .filter(|&r| glyphs.is_easy(r))
                 .count()
         })
         .sum()
 }
 
 fn solve_part2(input: &str) -> u32 {
-    input.trim().lines().map(decode_line).sum()
+    input
+        .trim()
+        .lines()
+        .map(|line| decode_line(line).unwrap())
+        .sum()
 }
 
 /// Decodes a line and returns its output value.
-fn decode_line(line: &str) -> u32 {
+fn decode_line(line: &str) -> Result<u32, DecodeError> {
     let signals: Vec<_> = line
         .split_ascii_whitespace()
         .filter(|&s| s != "|")
         .collect();
-    let digits = decode(&signals);
+    let digits = decode(&signals)?;
 
     let idx = digits.len() - 4;
 
-    digits[idx] * 1000 + digits[idx + 1] * 100 + digits[idx + 2] * 10 + digits[idx + 3]
+    Ok(digits[idx] * 1000 + digits[idx + 1] * 100 + digits[idx + 2] * 10 + digits[idx + 3])
 }
 
-fn decode(signals: &[&str]) -> Vec<u32> {
-    let mut infos: Vec<_> = signals.iter().map(|&s| DecodeInfo::new(s)).collect();
+/// Works out the wiring from all the signals on a line and decodes each of
+/// them into a digit.
+fn decode(signals: &[&str]) -> Result<Vec<u32>, DecodeError> {
+    let glyphs = GlyphSet::seven_segment().decode(signals)?;
+    Ok(glyphs.iter().map(|g| g.to_digit(10).unwrap()).collect())
+}
 
-    while !infos.iter().all(|x| x.decoded()) {
-        for i in 0..infos.len() {
-            if infos[i].decoded() {
-                continue;
-            }
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidSignal(String),
+    /// No wiring makes every signal a glyph.
+    Unsolvable,
+    /// More than one wiring fits the signals.
+    Ambiguous,
+}
 
-            let mut info = infos[i].clone();
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidSignal(s) => write!(f, "invalid signal {:?}", s),
+            DecodeError::Unsolvable => write!(f, "no wiring matches the signals"),
+            DecodeError::Ambiguous => write!(f, "more than one wiring matches the signals"),
+        }
+    }
+}
 
-            for other in infos.iter().filter(|&info| info.decoded()) {
-                info.try_deduce(other);
-            }
+impl std::error::Error for DecodeError {}
 
-            infos[i] = info;
+/// The glyphs a display can show, each as a bitmask of the segments it
+/// lights. Segments (and wires) are named with letters from `a`, with `a` as
+/// the lowest bit.
+#[derive(Clone, Debug)]
+pub struct GlyphSet {
+    segments: usize,
+    glyphs: Vec<(char, u32)>,
+}
+
+impl GlyphSet {
+    /// Builds a glyph set from (name, segments) pairs like `('1', "cf")`.
+    ///
+    /// Panics if there are more than 26 segments or 64 glyphs, if a glyph
+    /// uses a segment out of range, or if two glyphs look the same.
+    pub fn new(segments: usize, glyphs: &[(char, &str)]) -> GlyphSet {
+        assert!(segments <= 26, "at most 26 segments are supported");
+        assert!(glyphs.len() <= 64, "at most 64 glyphs are supported");
+
+        let mut set = GlyphSet {
+            segments,
+            glyphs: vec![],
+        };
+        for &(name, lit) in glyphs {
+            let mask = set
+                .parse(lit)
+                .unwrap_or_else(|| panic!("invalid segments {:?} for {:?}", lit, name));
+            assert!(
+                set.glyph(mask).is_none(),
+                "glyph {:?} duplicates another",
+                name
+            );
+            set.glyphs.push((name, mask));
         }
+        set
     }
 
-    infos.iter().map(|i| i.digit()).collect()
-}
+    /// The digits 0 through 9 on a standard seven-segment display.
+    pub fn seven_segment() -> GlyphSet {
+        GlyphSet::new(7, &SEVEN_SEGMENT_DIGITS)
+    }
 
-#[derive(Clone)]
-pub struct DecodeInfo {
-    pub segments: HashSet<char>,
-    pub possibilities: HashSet<u32>,
-}
+    /// The seven-segment digits plus `A`, `b`, `C`, `d`, `E` and `F`.
+    pub fn hexadecimal() -> GlyphSet {
+        let mut glyphs = SEVEN_SEGMENT_DIGITS.to_vec();
+        glyphs.extend([
+            ('A', "abcdef"),
+            ('b', "bdefg"),
+            ('C', "abeg"),
+            ('d', "cdefg"),
+            ('E', "abdeg"),
+            ('F', "abde"),
+        ]);
+        GlyphSet::new(7, &glyphs)
+    }
+
+    pub fn segments(&self) -> usize {
+        self.segments
+    }
+
+    /// Converts a signal such as "cfa" into a bitmask of its wires.
+    pub fn parse(&self, signal: &str) -> Option<u32> {
+        signal.chars().try_fold(0, |mask, c| {
+            let wire = (c as u32).checked_sub('a' as u32)?;
+            if wire as usize >= self.segments || mask & (1 << wire) > 0 {
+                None
+            } else {
+                Some(mask | (1 << wire))
+            }
+        })
+    }
+
+    /// The glyph with exactly these segments lit, if any.
+    pub fn glyph(&self, segments: u32) -> Option<char> {
+        self.glyphs
+            .iter()
+            .find(|&&(_, mask)| mask == segments)
+            .map(|&(name, _)| name)
+    }
+
+    /// Whether a signal can be identified from its length alone, because
+    /// only one glyph lights that many segments. On the standard display,
+    /// these are 1, 4, 7, and 8.
+    pub fn is_easy(&self, signal: &str) -> bool {
+        let len = signal.len() as u32;
+        self.glyphs
+            .iter()
+            .filter(|&&(_, mask)| mask.count_ones() == len)
+            .count()
+            == 1
+    }
+
+    /// Works out the wiring from the signals and decodes each of them.
+    pub fn decode(&self, signals: &[&str]) -> Result<Vec<char>, DecodeError> {
+        let masks = signals
+            .iter()
+            .map(|&s| {
+                self.parse(s)
+                    .ok_or_else(|| DecodeError::InvalidSignal(s.to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
 
-impl DecodeInfo {
-    pub fn new(s: &str) -> DecodeInfo {
-        let segments: HashSet<char> = s.chars().collect();
+        let wiring = self.solve(&masks)?;
 
-        let possibilities: HashSet<u32> = match segments.len() {
-            2 => HashSet::from([1]),
-            4 => HashSet::from([4]),
-            3 => HashSet::from([7]),
-            7 => HashSet::from([8]),
-            5 => HashSet::from([2, 3, 5]),
-            6 => HashSet::from([0, 6, 9]),
-            _ => panic!("What the heck digit is this?"),
+        Ok(masks
+            .iter()
+            .map(|&m| self.glyph(wiring.translate(m)).unwrap())
+            .collect())
+    }
+
+    /// Finds the one wiring which turns every signal into a glyph.
+    ///
+    /// Each wire starts out able to be any segment. Every signal narrows
+    /// that down: a wire in the signal must be a segment lit by some glyph
+    /// with the same number of segments, and a wire not in it must be one
+    /// that's unlit in one of those glyphs. We then try the remaining
+    /// choices for each wire, dropping any glyph a signal can no longer be
+    /// as we go.
+    ///
+    /// ```
+    /// use aoc::day08::GlyphSet;
+    /// let glyphs = GlyphSet::seven_segment();
+    /// let signals = ["acedgfb", "cdfbe", "gcdfa", "fbcad", "dab", "cefabd", "cdfgeb", "eafb", "cagedb", "ab"];
+    /// let masks: Vec<u32> = signals.iter().map(|s| glyphs.parse(s).unwrap()).collect();
+    /// assert_eq!(glyphs.solve(&masks).unwrap().to_string(), "cfgabde");
+    /// ```
+    pub fn solve(&self, signals: &[u32]) -> Result<Wiring, DecodeError> {
+        let all_segments = ((1u64 << self.segments) - 1) as u32;
+
+        // The glyphs each signal could be, as a bitmask of indices into
+        // self.glyphs.
+        let candidates: Vec<u64> = signals
+            .iter()
+            .map(|&s| self.matching(u64::MAX, |mask| mask.count_ones() == s.count_ones()))
+            .collect();
+
+        let mut domains = vec![all_segments; self.segments];
+        for (&signal, &cands) in signals.iter().zip(&candidates) {
+            for (wire, domain) in domains.iter_mut().enumerate() {
+                let lit = signal & (1 << wire) > 0;
+                let possible = self
+                    .indices(cands)
+                    .map(|g| self.glyphs[g].1)
+                    .fold(0, |acc, mask| acc | if lit { mask } else { !mask });
+                *domain &= possible & all_segments;
+            }
+        }
+
+        let mut search = Search {
+            glyphs: self,
+            signals,
+            domains,
+            assigned: vec![None; self.segments],
+            used: 0,
+            found: vec![],
         };
+        search.run(candidates);
 
-        DecodeInfo {
-            segments,
-            possibilities,
+        match search.found.len() {
+            0 => Err(DecodeError::Unsolvable),
+            1 => Ok(search.found.pop().unwrap()),
+            _ => Err(DecodeError::Ambiguous),
         }
     }
 
-    pub fn digit(&self) -> u32 {
-        assert!(self.possibilities.len() == 1);
-        *self.possibilities.iter().next().unwrap()
+    fn indices(&self, set: u64) -> impl Iterator<Item = usize> {
+        (0..self.glyphs.len()).filter(move |&g| set & (1 << g) > 0)
     }
 
-    pub fn decoded(&self) -> bool {
-        self.possibilities.len() == 1
+    /// The glyphs in `set` whose segments satisfy `pred`.
+    fn matching(&self, set: u64, pred: impl Fn(u32) -> bool) -> u64 {
+        self.indices(set)
+            .filter(|&g| pred(self.glyphs[g].1))
+            .fold(0, |acc, g| acc | (1 << g))
     }
+}
 
-    pub fn try_deduce(&mut self, info: &DecodeInfo) {
-        self.possibilities.drain_filter(|p| {
-            let expected = expected_intersections(*p, info.digit());
-            let actual = self.segments.intersection(&info.segments).count();
-            expected != actual
-        });
-    }
+const SEVEN_SEGMENT_DIGITS: [(char, &str); 10] = [
+    ('0', "abcefg"),
+    ('1', "cf"),
+    ('2', "acdeg"),
+    ('3', "acdfg"),
+    ('4', "bcdf"),
+    ('5', "abdfg"),
+    ('6', "abdefg"),
+    ('7', "acf"),
+    ('8', "abcdefg"),
+    ('9', "abcdfg"),
+];
+
+/// Which segment each wire is actually connected to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Wiring {
+    segments: Vec<usize>,
 }
 
-/// The digits 1, 4, 7, and 8 all have a unique number of segments used in
-/// their display: no other digit uses 2 segments, for example. As a result,
-/// we consider these "easy digits" which we can always guess.
-fn is_easy_digit(s: &str) -> bool {
-    matches!(s.len(), 2 | 3 | 4 | 7)
+impl Wiring {
+    /// The segment that wire `wire` lights up, both as letters.
+    pub fn segment(&self, wire: char) -> Option<char> {
+        let idx = (wire as u32).checked_sub('a' as u32)? as usize;
+        let segment = *self.segments.get(idx)?;
+        char::from_u32('a' as u32 + segment as u32)
+    }
+
+    /// Translates a mask of wires into the mask of segments they light.
+    pub fn translate(&self, wires: u32) -> u32 {
+        (0..self.segments.len())
+            .filter(|w| wires & (1 << w) > 0)
+            .fold(0, |mask, w| mask | (1 << self.segments[w]))
+    }
 }
 
-/// Converts a digit back to the (good, non-garbled) segments.
-fn digit_to_segments(a: u32) -> HashSet<char> {
-    match a {
-        0 => "abcefg".chars().collect(),
-        1 => "cf".chars().collect(),
-        2 => "acdeg".chars().collect(),
-        3 => "acdfg".chars().collect(),
-        4 => "bcdf".chars().collect(),
-        5 => "abdfg".chars().collect(),
-        6 => "abdefg".chars().collect(),
-        7 => "acf".chars().collect(),
-        8 => "abcdefg".chars().collect(),
-        _ => "abcdfg".chars().collect(),
+/// Shows the segment for each wire in order, so "cfgabde" means wire `a`
+/// lights segment `c`, and so on.
+impl fmt::Display for Wiring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &segment in &self.segments {
+            write!(f, "{}", (b'a' + segment as u8) as char)?;
+        }
+        Ok(())
     }
 }
 
-fn expected_intersections(a: u32, b: u32) -> usize {
-    digit_to_segments(a)
-        .intersection(&digit_to_segments(b))
-        .count()
+/// Backtracking search over the segment for each wire. Stops once it has
+/// found two wirings, since that's enough to know it's ambiguous.
+struct Search<'a> {
+    glyphs: &'a GlyphSet,
+    signals: &'a [u32],
+    domains: Vec<u32>,
+    assigned: Vec<Option<usize>>,
+    used: u32,
+    found: Vec<Wiring>,
+}
+
+impl Search<'_> {
+    fn run(&mut self, candidates: Vec<u64>) {
+        if self.found.len() > 1 {
+            return;
+        }
+
+        // Pick the unassigned wire with the fewest options left.
+        let next = (0..self.assigned.len())
+            .filter(|&w| self.assigned[w].is_none())
+            .min_by_key(|&w| (self.domains[w] & !self.used).count_ones());
+
+        let wire = match next {
+            Some(wire) => wire,
+            None => {
+                self.found.push(Wiring {
+                    segments: self.assigned.iter().map(|s| s.unwrap()).collect(),
+                });
+                return;
+            }
+        };
+
+        for segment in 0..self.assigned.len() {
+            if (self.domains[wire] & !self.used) & (1 << segment) == 0 {
+                continue;
+            }
+
+            // Keep only the glyphs that agree with this choice.
+            let narrowed: Vec<u64> = self
+                .signals
+                .iter()
+                .zip(&candidates)
+                .map(|(&signal, &cands)| {
+                    let lit = signal & (1 << wire) > 0;
+                    self.glyphs
+                        .matching(cands, |mask| (mask & (1 << segment) > 0) == lit)
+                })
+                .collect();
+            if narrowed.contains(&0) {
+                continue;
+            }
+
+            self.assigned[wire] = Some(segment);
+            self.used |= 1 << segment;
+            self.run(narrowed);
+            self.used &= !(1 << segment);
+            self.assigned[wire] = None;
+        }
+    }
 }
 
 fn puzzle_input() -> &'static str {
@@ -139,6 +360,7 @@ fn puzzle_input() -> &'static str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::XorShift;
 
     fn example_input() -> &'static str {
         include_str!("../inputs/day08_example.txt")
@@ -156,9 +378,137 @@ mod test {
         let signals: Vec<_> = line.split(' ').collect();
 
         assert_eq!(
-            vec![8, 5, 2, 3, 7, 9, 6, 4, 0, 1, 5, 3, 5, 3],
+            Ok(vec![8, 5, 2, 3, 7, 9, 6, 4, 0, 1, 5, 3, 5, 3]),
             decode(&signals)
         );
-        assert_eq!(5353, decode_line(line));
+        assert_eq!(Ok(5353), decode_line(line));
+    }
+
+    #[test]
+    fn reports_the_wiring() {
+        let glyphs = GlyphSet::seven_segment();
+        let signals: Vec<u32> = "acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab"
+            .split(' ')
+            .map(|s| glyphs.parse(s).unwrap())
+            .collect();
+        let wiring = glyphs.solve(&signals).unwrap();
+
+        assert_eq!(Some('c'), wiring.segment('a'));
+        assert_eq!(Some('f'), wiring.segment('b'));
+        assert_eq!(
+            Some('8'),
+            glyphs.glyph(wiring.translate(glyphs.parse("abcdefg").unwrap()))
+        );
+    }
+
+    #[test]
+    fn derives_easy_glyphs_from_the_table() {
+        let easy = |glyphs: &GlyphSet| {
+            (1..=7)
+                .filter(|&n| glyphs.is_easy(&"abcdefg"[..n]))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(vec![2, 3, 4, 7], easy(&GlyphSet::seven_segment()));
+        assert_eq!(vec![2, 3, 7], easy(&GlyphSet::hexadecimal()));
+    }
+
+    /// Lights up every glyph through a scrambled wiring, returning the
+    /// signals as letters.
+    fn scramble(glyphs: &GlyphSet, wiring: &[usize]) -> Vec<String> {
+        glyphs
+            .glyphs
+            .iter()
+            .map(|&(_, mask)| {
+                (0..wiring.len())
+                    .filter(|&w| mask & (1 << wiring[w]) > 0)
+                    .map(|w| (b'a' + w as u8) as char)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_hexadecimal_displays() {
+        let glyphs = GlyphSet::hexadecimal();
+        let wiring = [3, 6, 0, 5, 1, 4, 2];
+        let signals = scramble(&glyphs, &wiring);
+        let signals: Vec<&str> = signals.iter().map(|s| s.as_str()).collect();
+
+        assert_eq!(
+            "0123456789AbCdEF",
+            glyphs.decode(&signals).unwrap().iter().collect::<String>()
+        );
+        assert_eq!(
+            Ok(vec!['F', 'A', 'C', 'E']),
+            glyphs
+                .decode(
+                    &[
+                        &signals,
+                        &[signals[15], signals[10], signals[12], signals[14]][..]
+                    ]
+                    .concat()
+                )
+                .map(|d| d[16..].to_vec())
+        );
+    }
+
+    #[test]
+    fn decodes_fourteen_segment_displays() {
+        // Random glyph tables either have one wiring, which must be the one
+        // we scrambled with, or are ambiguous; they're never unsolvable.
+        let mut rng = XorShift::new(0x853c49e6748fea9b);
+        let mut next = move || rng.next_u64();
+
+        let mut solved = 0;
+        for _ in 0..50 {
+            let letters = "abcdefghijklmn";
+            let table: Vec<(char, String)> = (0..20)
+                .map(|i| {
+                    let mask = next() & 0x3fff;
+                    let lit = (0..14)
+                        .filter(|b| mask & (1 << b) > 0)
+                        .map(|b| letters.as_bytes()[b] as char)
+                        .collect();
+                    ((b'A' + i) as char, lit)
+                })
+                .collect();
+            let mut table: Vec<(char, &str)> =
+                table.iter().map(|(c, s)| (*c, s.as_str())).collect();
+            table.sort_by_key(|&(_, s)| s);
+            table.dedup_by_key(|&mut (_, s)| s);
+            let glyphs = GlyphSet::new(14, &table);
+
+            let mut wiring: Vec<usize> = (0..14).collect();
+            for i in (1..14).rev() {
+                wiring.swap(i, next() as usize % (i + 1));
+            }
+            let signals: Vec<u32> = scramble(&glyphs, &wiring)
+                .iter()
+                .map(|s| glyphs.parse(s).unwrap())
+                .collect();
+
+            match glyphs.solve(&signals) {
+                Ok(found) => {
+                    assert_eq!(wiring, found.segments);
+                    solved += 1;
+                }
+                Err(e) => assert_eq!(DecodeError::Ambiguous, e),
+            }
+        }
+        assert!(solved > 40);
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        assert_eq!(Err(DecodeError::Ambiguous), decode(&["ab", "abc"]));
+        assert_eq!(Err(DecodeError::Unsolvable), decode(&["ab", "cd"]));
+        assert_eq!(
+            Err(DecodeError::Unsolvable),
+            decode(&["abcde", "abcdf", "abcdg", "abcef"])
+        );
+        assert_eq!(
+            Err(DecodeError::InvalidSignal("abz".to_string())),
+            decode(&["abz"])
+        );
     }
 }
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::fmt;
 
 pub fn run() {
     let input = puzzle_input();
@@ -7,7 +7,7 @@ pub fn run() {
 }
 
 fn solve_part1(input: &str) -> u32 {
-    let heightmap = Heightmap::from_input(input);
+    let heightmap = Heightmap::from_input(input).unwrap();
     heightmap
         .low_points()
         .iter()
@@ -16,39 +16,161 @@ fn solve_part1(input: &str) -> u32 {
 }
 
 fn solve_part2(input: &str) -> u32 {
-    let heightmap = Heightmap::from_input(input);
-    let mut basin_sizes: Vec<_> = heightmap
-        .low_points()
-        .iter()
-        .map(|&(row, col)| heightmap.basin_size(row, col))
-        .collect();
+    let heightmap = Heightmap::from_input(input).unwrap();
+    let mut basin_sizes = heightmap.basins().sizes();
     basin_sizes.sort_unstable_by(|a, b| b.cmp(a));
 
-    basin_sizes.iter().take(3).product()
+    basin_sizes.iter().take(3).map(|&s| s as u32).product()
+}
+
+/// Which locations count as adjacent to each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Neighborhood {
+    /// Up, down, left, and right, as in the puzzle.
+    Four,
+    /// The four above plus the diagonals.
+    Eight,
+}
+
+impl Neighborhood {
+    fn offsets(self) -> &'static [(isize, isize)] {
+        match self {
+            Neighborhood::Four => &[(-1, 0), (1, 0), (0, -1), (0, 1)],
+            Neighborhood::Eight => &[
+                (-1, -1),
+                (-1, 0),
+                (-1, 1),
+                (0, -1),
+                (0, 1),
+                (1, -1),
+                (1, 0),
+                (1, 1),
+            ],
+        }
+    }
+
+    /// The locations next to this one which are within a `rows` by
+    /// `columns` map.
+    fn around(
+        self,
+        row: usize,
+        col: usize,
+        rows: usize,
+        columns: usize,
+    ) -> impl Iterator<Item = (usize, usize)> {
+        self.offsets().iter().filter_map(move |&(dr, dc)| {
+            let r = row.checked_add_signed(dr).filter(|&r| r < rows)?;
+            let c = col.checked_add_signed(dc).filter(|&c| c < columns)?;
+            Some((r, c))
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum HeightmapError {
+    Empty,
+    /// A row's length differs from the first row's.
+    Ragged {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidHeight {
+        line: usize,
+        column: usize,
+        found: char,
+    },
 }
 
-struct Heightmap {
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeightmapError::Empty => write!(f, "heightmap is empty"),
+            HeightmapError::Ragged {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {}: expected {} heights, found {}",
+                line, expected, found
+            ),
+            HeightmapError::InvalidHeight {
+                line,
+                column,
+                found,
+            } => {
+                write!(
+                    f,
+                    "line {}, column {}: invalid height {:?}",
+                    line, column, found
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeightmapError {}
+
+pub struct Heightmap {
     heights: Vec<u8>,
     rows: usize,
     columns: usize,
+    neighborhood: Neighborhood,
 }
 
 impl Heightmap {
-    pub fn from_input(input: &str) -> Self {
-        let heights: Vec<_> = input
-            .trim()
-            .lines()
-            .flat_map(|line| line.chars().map(|c| c.to_digit(10).unwrap() as u8))
-            .collect();
+    /// Parses a heightmap with one row of digits per line. Blank lines are
+    /// skipped, and every other row must be the same length; line and column
+    /// numbers in errors count from 1 and include the blank lines.
+    pub fn from_input(input: &str) -> Result<Self, HeightmapError> {
+        let mut heights = vec![];
+        let mut rows = 0;
+        let mut columns = 0;
+
+        for (idx, line) in input.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
 
-        let columns = input.lines().next().unwrap().len();
-        let rows = heights.len() / columns;
+            for (col, c) in line.chars().enumerate() {
+                let height = c.to_digit(10).ok_or(HeightmapError::InvalidHeight {
+                    line: idx + 1,
+                    column: col + 1,
+                    found: c,
+                })?;
+                heights.push(height as u8);
+            }
+
+            let found = line.chars().count();
+            if rows == 0 {
+                columns = found;
+            } else if found != columns {
+                return Err(HeightmapError::Ragged {
+                    line: idx + 1,
+                    expected: columns,
+                    found,
+                });
+            }
+            rows += 1;
+        }
 
-        Heightmap {
+        if heights.is_empty() {
+            return Err(HeightmapError::Empty);
+        }
+
+        Ok(Heightmap {
             heights,
             rows,
             columns,
-        }
+            neighborhood: Neighborhood::Four,
+        })
+    }
+
+    pub fn with_neighborhood(mut self, neighborhood: Neighborhood) -> Self {
+        self.neighborhood = neighborhood;
+        self
     }
 
     pub fn low_points(&self) -> Vec<(usize, usize)> {
@@ -67,33 +189,95 @@ impl Heightmap {
             .collect()
     }
 
-    /// Computes the basin size for a provided low point. This relies on the
-    /// assumption that each basin is separated by a ridge of 9s, so we can do
-    /// a breadth-first search outward until we find the ridge.
-    pub fn basin_size(&self, row: usize, col: usize) -> u32 {
-        let mut prev_fringe: HashSet<(usize, usize)> = HashSet::new();
-        let mut fringe: HashSet<_> = HashSet::from([(row, col)]);
-
-        while fringe != prev_fringe {
-            prev_fringe = fringe.clone();
+    /// Labels every cell with the basin it drains into. Neighboring cells
+    /// of the same height form a plateau (often just one cell), and each
+    /// plateau flows to the lowest location next to any of its cells, if
+    /// that's lower than it. Plateaus with nowhere lower to go are the
+    /// bottoms of basins. Locations of height 9 aren't in any basin.
+    ///
+    /// This doesn't rely on basins being separated by ridges of 9s. When
+    /// they are, and each basin has one low point as the puzzle promises,
+    /// it gives the same basins.
+    pub fn basins(&self) -> Basins {
+        let cells = self.rows * self.columns;
 
-            for &(row, col) in &prev_fringe {
-                if (row > 0) && self.get(row - 1, col) != 9 {
-                    fringe.insert((row - 1, col));
+        // Join up each plateau first, so it can drain as one.
+        let mut plateaus: Vec<usize> = (0..cells).collect();
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                let height = self.get(row, col);
+                if height == 9 {
+                    continue;
+                }
+                for (r, c) in self.neighbors(row, col) {
+                    if self.get(r, c) == height {
+                        union(
+                            &mut plateaus,
+                            col + row * self.columns,
+                            c + r * self.columns,
+                        );
+                    }
                 }
-                if (row < self.rows - 1) && self.get(row + 1, col) != 9 {
-                    fringe.insert((row + 1, col));
+            }
+        }
+
+        // The lowest location below each plateau, by the plateau's root.
+        let mut lowest: Vec<Option<(u8, usize)>> = vec![None; cells];
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                let height = self.get(row, col);
+                if height == 9 {
+                    continue;
                 }
-                if (col > 0) && self.get(row, col - 1) != 9 {
-                    fringe.insert((row, col - 1));
+                let root = find(&mut plateaus, col + row * self.columns);
+
+                for (r, c) in self.neighbors(row, col) {
+                    let neighbor = self.get(r, c);
+                    let below = lowest[root].map_or(height, |(h, _)| h);
+                    if neighbor < below {
+                        lowest[root] = Some((neighbor, c + r * self.columns));
+                    }
                 }
-                if (col < self.columns - 1) && self.get(row, col + 1) != 9 {
-                    fringe.insert((row, col + 1));
+            }
+        }
+
+        let mut parent = plateaus;
+        for (root, below) in lowest.into_iter().enumerate() {
+            if let Some((_, target)) = below {
+                union(&mut parent, root, target);
+            }
+        }
+
+        let mut labels = vec![None; cells];
+        let mut members: Vec<Vec<(usize, usize)>> = vec![];
+        let mut ids = vec![None; cells];
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                if self.get(row, col) == 9 {
+                    continue;
                 }
+                let root = find(&mut parent, col + row * self.columns);
+                let id = *ids[root].get_or_insert_with(|| {
+                    members.push(vec![]);
+                    members.len() - 1
+                });
+                labels[col + row * self.columns] = Some(id);
+                members[id].push((row, col));
             }
         }
 
-        fringe.len() as u32
+        Basins {
+            labels,
+            members,
+            rows: self.rows,
+            columns: self.columns,
+            neighborhood: self.neighborhood,
+        }
+    }
+
+    /// The locations next to this one which are on the map.
+    fn neighbors(&self, row: usize, col: usize) -> impl Iterator<Item = (usize, usize)> {
+        self.neighborhood.around(row, col, self.rows, self.columns)
     }
 
     pub fn get(&self, row: usize, col: usize) -> u8 {
@@ -102,10 +286,98 @@ impl Heightmap {
 
     pub fn is_low_point(&self, row: usize, col: usize) -> bool {
         let height = self.get(row, col);
-        (row == 0 || height < self.get(row - 1, col))
-            && (row == self.rows - 1 || height < self.get(row + 1, col))
-            && (col == 0 || height < self.get(row, col - 1))
-            && (col == self.columns + 1 || height < self.get(row, col + 1))
+        self.neighbors(row, col)
+            .all(|(r, c)| height < self.get(r, c))
+    }
+}
+
+fn find(parent: &mut [usize], mut idx: usize) -> usize {
+    while parent[idx] != idx {
+        parent[idx] = parent[parent[idx]];
+        idx = parent[idx];
+    }
+    idx
+}
+
+fn union(parent: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parent, a), find(parent, b));
+    parent[a] = b;
+}
+
+/// Which basin each location on a heightmap belongs to.
+pub struct Basins {
+    labels: Vec<Option<usize>>,
+    members: Vec<Vec<(usize, usize)>>,
+    rows: usize,
+    columns: usize,
+    neighborhood: Neighborhood,
+}
+
+/// Background colors used to tell basins apart in `render`.
+const COLORS: [u8; 6] = [41, 42, 43, 44, 45, 46];
+
+impl Basins {
+    /// The number of basins.
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// The basin this location is in, or `None` for height 9.
+    pub fn label(&self, row: usize, col: usize) -> Option<usize> {
+        self.labels[col + row * self.columns]
+    }
+
+    pub fn size(&self, basin: usize) -> usize {
+        self.members[basin].len()
+    }
+
+    pub fn sizes(&self) -> Vec<usize> {
+        self.members.iter().map(|m| m.len()).collect()
+    }
+
+    /// The locations in a basin, in reading order.
+    pub fn members(&self, basin: usize) -> &[(usize, usize)] {
+        &self.members[basin]
+    }
+
+    /// The locations in a basin which are next to a location outside it,
+    /// whether that's a ridge or another basin. The edge of the map doesn't
+    /// count.
+    pub fn boundary(&self, basin: usize) -> Vec<(usize, usize)> {
+        self.members[basin]
+            .iter()
+            .copied()
+            .filter(|&(row, col)| {
+                self.neighborhood
+                    .around(row, col, self.rows, self.columns)
+                    .any(|(r, c)| self.label(r, c) != Some(basin))
+            })
+            .collect()
+    }
+
+    /// Draws the heightmap with each basin in its own color, using ANSI
+    /// escape codes. Ridges are left uncolored.
+    pub fn render(&self, heightmap: &Heightmap) -> String {
+        let mut out = String::new();
+        for row in 0..self.rows {
+            for col in 0..self.columns {
+                let height = heightmap.get(row, col);
+                match self.label(row, col) {
+                    Some(id) => out.push_str(&format!(
+                        "\x1b[30;{}m{}\x1b[0m",
+                        COLORS[id % COLORS.len()],
+                        height
+                    )),
+                    None => out.push_str(&height.to_string()),
+                }
+            }
+            out.push('\n');
+        }
+        out
     }
 }
 
@@ -116,6 +388,7 @@ fn puzzle_input() -> &'static str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::XorShift;
 
     fn example_input() -> &'static str {
         include_str!("../inputs/day09_example.txt")
@@ -123,7 +396,7 @@ mod test {
 
     #[test]
     fn parses_input_correctly() {
-        let heightmap = Heightmap::from_input(example_input());
+        let heightmap = Heightmap::from_input(example_input()).unwrap();
         assert_eq!(5, heightmap.rows);
         assert_eq!(10, heightmap.columns);
         assert_eq!(2, heightmap.get(0, 0));
@@ -133,7 +406,7 @@ mod test {
 
     #[test]
     fn checks_low_points_correctly() {
-        let heightmap = Heightmap::from_input(example_input());
+        let heightmap = Heightmap::from_input(example_input()).unwrap();
         assert_eq!(true, heightmap.is_low_point(0, 1));
         assert_eq!(true, heightmap.is_low_point(2, 2));
         assert_eq!(false, heightmap.is_low_point(0, 0));
@@ -142,9 +415,65 @@ mod test {
 
     #[test]
     fn finds_basin_sizes() {
-        let heightmap = Heightmap::from_input(example_input());
-        assert_eq!(3, heightmap.basin_size(0, 1));
-        assert_eq!(14, heightmap.basin_size(2, 2));
+        let heightmap = Heightmap::from_input(example_input()).unwrap();
+        let basins = heightmap.basins();
+        assert_eq!(4, basins.len());
+        assert_eq!(3, basins.size(basins.label(0, 1).unwrap()));
+        assert_eq!(14, basins.size(basins.label(2, 2).unwrap()));
+        assert_eq!(None, basins.label(0, 2));
+    }
+
+    #[test]
+    fn lists_basin_members_and_boundaries() {
+        let heightmap = Heightmap::from_input(example_input()).unwrap();
+        let basins = heightmap.basins();
+        let top_left = basins.label(0, 1).unwrap();
+
+        assert_eq!(&[(0, 0), (0, 1), (1, 0)], basins.members(top_left));
+        assert_eq!(vec![(0, 1), (1, 0)], basins.boundary(top_left));
+    }
+
+    #[test]
+    fn splits_basins_without_ridges() {
+        // Two valleys meeting with no 9s between them. The middle column
+        // slopes down to the left.
+        let heightmap = Heightmap::from_input("10201\n21312\n").unwrap();
+        let basins = heightmap.basins();
+
+        assert_eq!(2, basins.len());
+        assert_eq!(Some(0), basins.label(1, 2));
+        assert_eq!(vec![6, 4], basins.sizes());
+    }
+
+    #[test]
+    fn drains_plateaus_down_their_slope() {
+        let heightmap = Heightmap::from_input("99999\n92219\n99999\n").unwrap();
+        let basins = heightmap.basins();
+        assert_eq!(vec![(1, 3)], heightmap.low_points());
+        assert_eq!(vec![3], basins.sizes());
+
+        let heightmap = Heightmap::from_input("221").unwrap();
+        assert_eq!(vec![3], heightmap.basins().sizes());
+    }
+
+    #[test]
+    fn merges_flat_basin_bottoms() {
+        let heightmap = Heightmap::from_input("3113\n3223\n").unwrap();
+        let basins = heightmap.basins();
+
+        assert_eq!(1, basins.len());
+        assert_eq!(8, basins.size(0));
+    }
+
+    #[test]
+    fn renders_basins_in_color() {
+        let heightmap = Heightmap::from_input("191\n").unwrap();
+        let basins = heightmap.basins();
+
+        assert_eq!(
+            "\x1b[30;41m1\x1b[0m9\x1b[30;42m1\x1b[0m\n",
+            basins.render(&heightmap)
+        );
     }
 
     #[test]
@@ -156,4 +485,129 @@ mod test {
     fn verify_example_input_part2() {
         assert_eq!(1134, solve_part2(example_input()));
     }
+
+    #[test]
+    fn finds_low_points_on_the_right_edge() {
+        let heightmap = Heightmap::from_input("21\n32\n").unwrap();
+        assert_eq!(vec![(0, 1)], heightmap.low_points());
+    }
+
+    #[test]
+    fn handles_single_row_and_column_maps() {
+        let row = Heightmap::from_input("3141\n").unwrap();
+        assert_eq!(vec![(0, 1), (0, 3)], row.low_points());
+
+        let column = Heightmap::from_input("3\n1\n4\n1\n").unwrap();
+        assert_eq!(vec![(1, 0), (3, 0)], column.low_points());
+
+        let single = Heightmap::from_input("5").unwrap();
+        assert_eq!(vec![(0, 0)], single.low_points());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert_eq!(
+            Err(HeightmapError::Ragged {
+                line: 2,
+                expected: 3,
+                found: 2
+            }),
+            Heightmap::from_input("123\n45\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(HeightmapError::Ragged {
+                line: 4,
+                expected: 3,
+                found: 2
+            }),
+            Heightmap::from_input("\n\n123\n45\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(HeightmapError::InvalidHeight {
+                line: 1,
+                column: 2,
+                found: 'x'
+            }),
+            Heightmap::from_input("1x3\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(HeightmapError::InvalidHeight {
+                line: 3,
+                column: 1,
+                found: 'x'
+            }),
+            Heightmap::from_input("123\n\nx56\n").map(|_| ())
+        );
+        assert_eq!(
+            Err(HeightmapError::Empty),
+            Heightmap::from_input("\n").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn uses_diagonal_neighbors() {
+        let input = "212\n099\n999\n";
+        let four = Heightmap::from_input(input).unwrap();
+        assert_eq!(vec![(0, 1), (1, 0)], four.low_points());
+
+        // The 1 at the top is diagonally next to the 0.
+        let eight = four.with_neighborhood(Neighborhood::Eight);
+        assert_eq!(vec![(1, 0)], eight.low_points());
+    }
+
+    fn brute_force_low_points(
+        heights: &[Vec<u8>],
+        neighborhood: Neighborhood,
+    ) -> Vec<(usize, usize)> {
+        let mut low = vec![];
+        for (row, line) in heights.iter().enumerate() {
+            for (col, &height) in line.iter().enumerate() {
+                let is_low = heights.iter().enumerate().all(|(r, other)| {
+                    other.iter().enumerate().all(|(c, &h)| {
+                        let (dr, dc) = (row.abs_diff(r), col.abs_diff(c));
+                        let adjacent = match neighborhood {
+                            Neighborhood::Four => dr + dc == 1,
+                            Neighborhood::Eight => dr.max(dc) == 1,
+                        };
+                        !adjacent || height < h
+                    })
+                });
+                if is_low {
+                    low.push((row, col));
+                }
+            }
+        }
+        low
+    }
+
+    #[test]
+    fn low_points_match_brute_force() {
+        let mut rng = XorShift::new(0x2545f4914f6cdd1d);
+        let mut next = move || rng.next_u64();
+
+        for _ in 0..500 {
+            let rows = 1 + next() as usize % 6;
+            let columns = 1 + next() as usize % 6;
+            let heights: Vec<Vec<u8>> = (0..rows)
+                .map(|_| (0..columns).map(|_| (next() % 10) as u8).collect())
+                .collect();
+            let input: String = heights
+                .iter()
+                .map(|row| row.iter().map(|h| h.to_string()).collect::<String>() + "\n")
+                .collect();
+
+            for neighborhood in [Neighborhood::Four, Neighborhood::Eight] {
+                let heightmap = Heightmap::from_input(&input)
+                    .unwrap()
+                    .with_neighborhood(neighborhood);
+                assert_eq!(
+                    brute_force_low_points(&heights, neighborhood),
+                    heightmap.low_points(),
+                    "{:?} on\n{}",
+                    neighborhood,
+                    input
+                );
+            }
+        }
+    }
 }
@@ -1,3 +1,6 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
 pub fn run() {
     let input = puzzle_input();
     println!("day10.part1.solution = {}", solve_part1(input));
@@ -12,6 +15,9 @@ fn solve_part1(input: &str) -> u64 {
             SyntaxError::Incomplete(_) => 0,
             SyntaxError::MismatchedChunk(_, c) => score_mismatch(c),
             SyntaxError::CloseBeforeOpen(c) => score_mismatch(c),
+            // The puzzle only has bracket characters, so it gives no score
+            // for anything else.
+            SyntaxError::Unknown(_) => 0,
         })
         .sum()
 }
@@ -39,35 +45,266 @@ enum SyntaxError {
     Incomplete(String),
     MismatchedChunk(char, char),
     CloseBeforeOpen(char),
+    Unknown(char),
 }
 
+/// Finds the first error in a line, for scoring.
 fn check_syntax(line: &str) -> Option<SyntaxError> {
-    let mut stack: Vec<char> = vec![];
-
-    for c in line.chars() {
-        if matches!(c, '(' | '[' | '{' | '<') {
-            stack.push(c);
-        } else if let Some(prev) = stack.pop() {
-            if !valid_pair(prev, c) {
-                return Some(SyntaxError::MismatchedChunk(prev, c));
+    let diagnostics = PairTable::standard().check(line);
+
+    match diagnostics.first()?.problem {
+        Problem::Mismatched { open, found, .. } => Some(SyntaxError::MismatchedChunk(open, found)),
+        Problem::Unopened { found } => Some(SyntaxError::CloseBeforeOpen(found)),
+        Problem::Unknown { found } => Some(SyntaxError::Unknown(found)),
+        Problem::Unclosed { .. } => {
+            // Only unclosed chunks are left, reported innermost first.
+            let stack = diagnostics
+                .iter()
+                .rev()
+                .filter_map(|d| match d.problem {
+                    Problem::Unclosed { open, .. } => Some(open),
+                    _ => None,
+                })
+                .collect();
+            Some(SyntaxError::Incomplete(stack))
+        }
+    }
+}
+
+/// The pairs of characters which open and close chunks.
+#[derive(Clone, Debug)]
+pub struct PairTable {
+    pairs: Vec<(char, char)>,
+}
+
+/// One problem in a line. Columns count characters from 1.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub column: usize,
+    pub problem: Problem,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Problem {
+    /// A chunk was closed with the wrong character.
+    Mismatched {
+        open: char,
+        opened_at: usize,
+        expected: char,
+        found: char,
+    },
+    /// A closing character with no chunk open.
+    Unopened { found: char },
+    /// A chunk still open at the end of the line. These are reported one
+    /// column past the end.
+    Unclosed {
+        open: char,
+        opened_at: usize,
+        expected: char,
+    },
+    /// A character which isn't in the pair table.
+    Unknown { found: char },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.problem {
+            Problem::Mismatched {
+                open,
+                opened_at,
+                expected,
+                found,
+            } => write!(
+                f,
+                "expected `{}`, found `{}` (to close `{}` from column {})",
+                expected, found, open, opened_at
+            ),
+            Problem::Unopened { found } => write!(f, "unexpected `{}` with no chunk open", found),
+            Problem::Unclosed {
+                open,
+                opened_at,
+                expected,
+            } => write!(
+                f,
+                "expected `{}`, found end of line (to close `{}` from column {})",
+                expected, open, opened_at
+            ),
+            Problem::Unknown { found } => write!(f, "unknown character `{}`", found),
+        }
+    }
+}
+
+impl PairTable {
+    /// Panics if a character appears in more than one place in the table.
+    pub fn new(pairs: &[(char, char)]) -> PairTable {
+        let mut seen: Vec<char> = vec![];
+        for &(open, close) in pairs {
+            assert!(open != close, "`{}` can't both open and close", open);
+            for c in [open, close] {
+                assert!(!seen.contains(&c), "`{}` is in the table twice", c);
+                seen.push(c);
             }
-        } else {
-            return Some(SyntaxError::CloseBeforeOpen(c));
         }
+
+        PairTable {
+            pairs: pairs.to_vec(),
+        }
+    }
+
+    /// The four pairs from the navigation subsystem: `()`, `[]`, `{}`, `<>`.
+    pub fn standard() -> PairTable {
+        PairTable::new(&[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>')])
     }
 
-    if !stack.is_empty() {
-        return Some(SyntaxError::Incomplete(stack.into_iter().collect()));
+    fn closer(&self, open: char) -> Option<char> {
+        self.pairs.iter().find(|p| p.0 == open).map(|p| p.1)
     }
 
-    None
+    fn is_closer(&self, c: char) -> bool {
+        self.pairs.iter().any(|p| p.1 == c)
+    }
+
+    /// Reports every problem in a line, in the order they're found.
+    ///
+    /// ```
+    /// use aoc::day10::{PairTable, Problem};
+    /// let problems = PairTable::standard().check("(]>");
+    /// assert_eq!(problems[0].column, 2);
+    /// assert_eq!(problems[1].problem, Problem::Unopened { found: '>' });
+    /// ```
+    pub fn check(&self, line: &str) -> Vec<Diagnostic> {
+        self.scan(line).0
+    }
+
+    /// Fixes every problem `check` reports, so the result has no errors:
+    /// wrong closing characters are replaced, stray ones and unknown
+    /// characters are dropped, and open chunks are closed at the end.
+    ///
+    /// ```
+    /// use aoc::day10::PairTable;
+    /// assert_eq!(PairTable::standard().repair("[(]>{"), "[()]{}");
+    /// ```
+    pub fn repair(&self, line: &str) -> String {
+        self.scan(line).1
+    }
+
+    /// Walks the line once, recovering from each error the same way that
+    /// `repair` fixes it.
+    fn scan(&self, line: &str) -> (Vec<Diagnostic>, String) {
+        let mut diagnostics = vec![];
+        let mut repaired = String::with_capacity(line.len());
+        let mut stack: Vec<(char, usize)> = vec![];
+        let mut len = 0;
+
+        for (idx, c) in line.chars().enumerate() {
+            let column = idx + 1;
+            len = column;
+
+            if self.closer(c).is_some() {
+                stack.push((c, column));
+                repaired.push(c);
+            } else if self.is_closer(c) {
+                match stack.pop() {
+                    Some((open, opened_at)) => {
+                        let expected = self.closer(open).unwrap();
+                        if c != expected {
+                            diagnostics.push(Diagnostic {
+                                column,
+                                problem: Problem::Mismatched {
+                                    open,
+                                    opened_at,
+                                    expected,
+                                    found: c,
+                                },
+                            });
+                        }
+                        repaired.push(expected);
+                    }
+                    None => diagnostics.push(Diagnostic {
+                        column,
+                        problem: Problem::Unopened { found: c },
+                    }),
+                }
+            } else {
+                diagnostics.push(Diagnostic {
+                    column,
+                    problem: Problem::Unknown { found: c },
+                });
+            }
+        }
+
+        while let Some((open, opened_at)) = stack.pop() {
+            let expected = self.closer(open).unwrap();
+            diagnostics.push(Diagnostic {
+                column: len + 1,
+                problem: Problem::Unclosed {
+                    open,
+                    opened_at,
+                    expected,
+                },
+            });
+            repaired.push(expected);
+        }
+
+        (diagnostics, repaired)
+    }
 }
 
-fn valid_pair(left: char, right: char) -> bool {
-    matches!(
-        (left, right),
-        ('(', ')') | ('[', ']') | ('{', '}') | ('<', '>')
-    )
+/// Counts from a run of `lint`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LintSummary {
+    pub corrupted: usize,
+    pub incomplete: usize,
+}
+
+/// Checks each line from `reader` with the standard pairs and writes
+/// compiler-style diagnostics to `out`, pointing at the column with a caret.
+/// Wrong or unknown characters are errors and make the line corrupted;
+/// chunks left open are warnings and make it incomplete. `name` is used in
+/// the location of each diagnostic.
+pub fn lint<R: BufRead, W: Write>(name: &str, reader: R, mut out: W) -> io::Result<LintSummary> {
+    let pairs = PairTable::standard();
+    let mut summary = LintSummary::default();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line_number = idx + 1;
+        let diagnostics = pairs.check(&line);
+
+        let corrupted = diagnostics
+            .iter()
+            .any(|d| !matches!(d.problem, Problem::Unclosed { .. }));
+        if corrupted {
+            summary.corrupted += 1;
+        } else if !diagnostics.is_empty() {
+            summary.incomplete += 1;
+        }
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        for diagnostic in diagnostics {
+            let level = match diagnostic.problem {
+                Problem::Unclosed { .. } => "warning",
+                _ => "error",
+            };
+            writeln!(out, "{}: {}", level, diagnostic)?;
+            writeln!(
+                out,
+                "{}--> {}:{}:{}",
+                gutter, name, line_number, diagnostic.column
+            )?;
+            writeln!(out, "{} |", gutter)?;
+            writeln!(out, "{} | {}", line_number, line)?;
+            writeln!(out, "{} | {}^", gutter, " ".repeat(diagnostic.column - 1))?;
+            writeln!(out)?;
+        }
+    }
+
+    writeln!(
+        out,
+        "{}: {} corrupted, {} incomplete",
+        name, summary.corrupted, summary.incomplete
+    )?;
+    Ok(summary)
 }
 
 fn score_mismatch(c: char) -> u64 {
@@ -122,6 +359,118 @@ mod test {
             Some(SyntaxError::Incomplete("[{".to_string()))
         );
         assert_eq!(check_syntax("[])"), Some(SyntaxError::CloseBeforeOpen(')')));
+        assert_eq!(check_syntax("(a)"), Some(SyntaxError::Unknown('a')));
+    }
+
+    #[test]
+    fn scores_unknown_characters_as_nothing() {
+        assert_eq!(57, solve_part1("(a)\n(]\n[x>\n"));
+    }
+
+    #[test]
+    fn reports_every_error_with_columns() {
+        let diagnostics = PairTable::standard().check("{([(<{}[<>[]}>{[]{[(<()>");
+
+        assert_eq!(
+            Diagnostic {
+                column: 13,
+                problem: Problem::Mismatched {
+                    open: '[',
+                    opened_at: 8,
+                    expected: ']',
+                    found: '}'
+                }
+            },
+            diagnostics[0]
+        );
+        // Recovering from that, the `>` closes the `<` properly, leaving
+        // eight chunks open.
+        assert_eq!(9, diagnostics.len());
+        assert_eq!(
+            Problem::Unclosed {
+                open: '(',
+                opened_at: 20,
+                expected: ')'
+            },
+            diagnostics[1].problem
+        );
+        assert!(diagnostics[1..]
+            .iter()
+            .all(|d| d.column == 25 && matches!(d.problem, Problem::Unclosed { .. })));
+    }
+
+    #[test]
+    fn repairs_lines() {
+        let pairs = PairTable::standard();
+        assert_eq!("()", pairs.repair("()"));
+        assert_eq!("[({})]", pairs.repair("[({"));
+        assert_eq!("()", pairs.repair(")(x"));
+
+        for line in example_input().lines() {
+            assert_eq!(Vec::<Diagnostic>::new(), pairs.check(&pairs.repair(line)));
+        }
+    }
+
+    #[test]
+    fn checks_custom_pairs() {
+        let pairs = PairTable::new(&[('a', 'b'), ('«', '»')]);
+        assert!(pairs.check("a«»b").is_empty());
+        assert_eq!(
+            vec![Diagnostic {
+                column: 3,
+                problem: Problem::Mismatched {
+                    open: '«',
+                    opened_at: 2,
+                    expected: '»',
+                    found: 'b'
+                }
+            }],
+            pairs.check("a«bb")
+        );
+        assert_eq!(
+            Problem::Unknown { found: '(' },
+            pairs.check("(").remove(0).problem
+        );
+        assert_eq!("a«»b", pairs.repair("a«b"));
+    }
+
+    #[test]
+    fn lints_a_file() {
+        let input = "()\n[(]\n{\n";
+        let mut out = vec![];
+        let summary = lint("nav.txt", input.as_bytes(), &mut out).unwrap();
+
+        assert_eq!(
+            LintSummary {
+                corrupted: 1,
+                incomplete: 1
+            },
+            summary
+        );
+        assert_eq!(
+            "\
+error: expected `)`, found `]` (to close `(` from column 2)
+ --> nav.txt:2:3
+  |
+2 | [(]
+  |   ^
+
+warning: expected `]`, found end of line (to close `[` from column 1)
+ --> nav.txt:2:4
+  |
+2 | [(]
+  |    ^
+
+warning: expected `}`, found end of line (to close `{` from column 1)
+ --> nav.txt:3:2
+  |
+3 | {
+  |  ^
+
+nav.txt: 1 corrupted, 1 incomplete
+",
+            String::from_utf8(out).unwrap()
+        );
     }
 
     #[test]
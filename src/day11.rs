@@ -5,101 +5,296 @@ pub fn run() {
 }
 
 fn solve_part1(input: &str) -> usize {
-    let mut grid = OctoGrid::parse(input);
-
-    let mut total = 0;
-
-    for _ in 0..100 {
-        total += grid.step();
-    }
-
-    total
+    FlashHistory::new(OctoGrid::parse(input)).flashes_after(100)
 }
 
 fn solve_part2(input: &str) -> usize {
-    let mut grid = OctoGrid::parse(input);
-    let mut round = 1;
-
-    while grid.step() != 100 {
-        round += 1;
-    }
+    FlashHistory::new(OctoGrid::parse(input))
+        .first_synchronized()
+        .expect("the octopi never all flash together")
+}
 
-    round
+/// How the edges of the grid behave.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Topology {
+    /// Octopi on the edges have fewer neighbors.
+    Bounded,
+    /// The grid wraps around, so the left edge neighbors the right edge and
+    /// the top neighbors the bottom.
+    Toroidal,
 }
 
 /// Represents all the octopi in the cavern.
-struct OctoGrid {
+#[derive(Clone)]
+pub struct OctoGrid {
     energy_levels: Vec<u32>,
     rows: usize,
     cols: usize,
+    topology: Topology,
+    /// Octopi which have flashed this step, in the order they flashed.
+    /// Kept between steps so that stepping doesn't allocate.
+    flashed: Vec<usize>,
 }
 
 impl OctoGrid {
+    /// Parses one row of energy levels per line. Blank lines and surrounding
+    /// whitespace are ignored, but every row must be the same length.
     pub fn parse(input: &str) -> Self {
-        let energy_levels: Vec<_> = input.chars().flat_map(|c| c.to_digit(10)).collect();
-        let size = (energy_levels.len() as f64).sqrt() as usize;
-        assert_eq!(size * size, energy_levels.len());
+        let mut energy_levels = vec![];
+        let mut rows = 0;
+        let mut cols = 0;
+
+        for line in input.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let row: Vec<_> = line.chars().map(|c| c.to_digit(10).unwrap()).collect();
+            if rows == 0 {
+                cols = row.len();
+            }
+            assert_eq!(cols, row.len(), "row {} has the wrong length", rows + 1);
+
+            energy_levels.extend(row);
+            rows += 1;
+        }
+
         OctoGrid {
             energy_levels,
-            rows: size,
-            cols: size,
+            rows,
+            cols,
+            topology: Topology::Bounded,
+            flashed: vec![],
         }
     }
 
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
     /// Performs one time-step and returns the number of flashes from this step.
+    ///
+    /// An octopus is queued to flash the moment its energy goes above 9, so
+    /// a cascade only visits the octopi it reaches and each flashes once.
     pub fn step(&mut self) -> usize {
-        for level in self.energy_levels.iter_mut() {
+        self.flashed.clear();
+
+        for (idx, level) in self.energy_levels.iter_mut().enumerate() {
             *level += 1;
+            if *level == 10 {
+                self.flashed.push(idx);
+            }
         }
 
-        let mut changed = true;
-        let mut flashed: Vec<bool> = vec![false; self.energy_levels.len()];
-
-        while changed {
-            changed = false;
-
-            for (idx, flash) in flashed.iter_mut().enumerate() {
-                if self.energy_levels[idx] > 9 && !*flash {
-                    changed = true;
-                    *flash = true;
+        // `flashed` doubles as the worklist: everything after `next` has
+        // yet to pass its flash on to its neighbors.
+        let mut next = 0;
+        while next < self.flashed.len() {
+            let idx = self.flashed[next];
+            next += 1;
 
-                    for neighbor in self.neighbors(idx) {
-                        self.energy_levels[neighbor] += 1;
-                    }
+            for neighbor in self.neighbors(idx) {
+                self.energy_levels[neighbor] += 1;
+                if self.energy_levels[neighbor] == 10 {
+                    self.flashed.push(neighbor);
                 }
             }
         }
 
-        for level in self.energy_levels.iter_mut() {
-            if *level > 9 {
-                *level = 0;
-            }
+        for &idx in &self.flashed {
+            self.energy_levels[idx] = 0;
         }
 
-        flashed.iter().filter(|&b| *b).count()
+        self.flashed.len()
     }
 
-    fn neighbors(&self, idx: usize) -> Vec<usize> {
-        let col = (idx % self.cols) as i32;
-        let row = ((idx - col as usize) / self.rows) as i32;
+    /// The distinct octopi adjacent to this one, including diagonally.
+    fn neighbors(&self, idx: usize) -> impl Iterator<Item = usize> {
+        let (row, col) = (idx / self.cols, idx % self.cols);
+        let (rows, cols, topology) = (self.rows, self.cols, self.topology);
+        let row_offsets = self.offsets(rows);
+        let col_offsets = self.offsets(cols);
 
-        let mut neighbors = vec![];
+        row_offsets
+            .iter()
+            .flat_map(move |&d_row| col_offsets.iter().map(move |&d_col| (d_row, d_col)))
+            .filter(|&offset| offset != (0, 0))
+            .filter_map(move |(d_row, d_col)| {
+                let (n_row, n_col) = match topology {
+                    Topology::Bounded => (
+                        row.checked_add_signed(d_row).filter(|&r| r < rows)?,
+                        col.checked_add_signed(d_col).filter(|&c| c < cols)?,
+                    ),
+                    Topology::Toroidal => (
+                        (row + rows).wrapping_add_signed(d_row) % rows,
+                        (col + cols).wrapping_add_signed(d_col) % cols,
+                    ),
+                };
+                Some(n_row * cols + n_col)
+            })
+    }
 
-        for d_row in -1..=1 {
-            for d_col in -1..=1 {
-                let (n_row, n_col) = (d_row + row, d_col + col);
-                if 0 <= n_row
-                    && n_row < self.rows as i32
-                    && 0 <= n_col
-                    && n_col < self.cols as i32
-                    && (n_row, n_col) != (row, col)
-                {
-                    neighbors.push((n_row as usize * self.rows) + n_col as usize);
-                }
+    /// The steps to take along a dimension of length `len` to reach each
+    /// neighbor. On a torus narrower than three, stepping back and forward
+    /// land on the same octopus (or on itself), so only one is kept.
+    fn offsets(&self, len: usize) -> &'static [isize] {
+        match (self.topology, len) {
+            (Topology::Toroidal, 1) => &[0],
+            (Topology::Toroidal, 2) => &[0, 1],
+            _ => &[-1, 0, 1],
+        }
+    }
+}
+
+/// The states of a grid repeat with this period, from the state after step
+/// `start` onward.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cycle {
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Steps a grid, keeping the number of flashes from each step. The grid is
+/// deterministic and has finitely many states, so it must eventually repeat
+/// one; from then on every step is known, and nothing more needs simulating.
+///
+/// Repeats are found with Brent's algorithm rather than by remembering every
+/// state, so only a few copies of the grid are ever kept, however long it
+/// takes to cycle.
+pub struct FlashHistory {
+    initial: OctoGrid,
+    grid: OctoGrid,
+    /// Flashes during each step, starting with step 1.
+    flashes: Vec<usize>,
+    /// The state after step `saved_at`, the latest power of two (or 0) before
+    /// the current step. Once the saved state is in the cycle, and the cycle
+    /// is no longer than `saved_at` steps, the grid comes back to it before
+    /// the next one is saved.
+    saved: Vec<u32>,
+    saved_at: usize,
+    cycle: Option<Cycle>,
+}
+
+impl FlashHistory {
+    pub fn new(grid: OctoGrid) -> Self {
+        FlashHistory {
+            initial: grid.clone(),
+            saved: grid.energy_levels.clone(),
+            saved_at: 0,
+            grid,
+            flashes: vec![],
+            cycle: None,
+        }
+    }
+
+    /// The flashes from each step simulated so far.
+    pub fn flashes(&self) -> &[usize] {
+        &self.flashes
+    }
+
+    /// Simulates until a state repeats and returns the cycle.
+    pub fn cycle(&mut self) -> Cycle {
+        while self.cycle.is_none() {
+            self.advance();
+        }
+        self.cycle.unwrap()
+    }
+
+    /// Takes one more step, unless the cycle is already known.
+    fn advance(&mut self) {
+        if self.cycle.is_some() {
+            return;
+        }
+
+        self.flashes.push(self.grid.step());
+        let step = self.flashes.len();
+
+        if self.grid.energy_levels == self.saved {
+            let length = step - self.saved_at;
+            self.cycle = Some(Cycle {
+                start: self.cycle_start(length),
+                length,
+            });
+        } else if step.is_power_of_two() {
+            self.saved.copy_from_slice(&self.grid.energy_levels);
+            self.saved_at = step;
+        }
+    }
+
+    /// The first step after which the state comes back `length` steps
+    /// later, found by running two grids from the start, `length` apart.
+    fn cycle_start(&self, length: usize) -> usize {
+        let mut behind = self.initial.clone();
+        let mut ahead = self.initial.clone();
+        for _ in 0..length {
+            ahead.step();
+        }
+
+        let mut start = 0;
+        while behind.energy_levels != ahead.energy_levels {
+            behind.step();
+            ahead.step();
+            start += 1;
+        }
+        start
+    }
+
+    /// Simulates until `step` is known, either directly or through the
+    /// cycle, and returns the flashes during it.
+    pub fn flashes_at(&mut self, step: usize) -> usize {
+        assert!(step > 0, "steps count from 1");
+        while self.flashes.len() < step && self.cycle.is_none() {
+            self.advance();
+        }
+
+        match self.cycle {
+            Some(Cycle { start, length }) if step > start + length => {
+                self.flashes[start + (step - start - 1) % length]
             }
+            _ => self.flashes[step - 1],
+        }
+    }
+
+    /// The total flashes over the first `steps` steps.
+    pub fn flashes_after(&mut self, steps: usize) -> usize {
+        while self.flashes.len() < steps && self.cycle.is_none() {
+            self.advance();
         }
 
-        neighbors
+        match self.cycle {
+            Some(Cycle { start, length }) if steps > start + length => {
+                let per_cycle: usize = self.flashes[start..start + length].iter().sum();
+                let (cycles, rest) = ((steps - start) / length, (steps - start) % length);
+                self.flashes[..start + rest].iter().sum::<usize>() + cycles * per_cycle
+            }
+            _ => self.flashes[..steps].iter().sum(),
+        }
+    }
+
+    /// The first step during which every octopus flashes, or `None` if the
+    /// grid cycles without that ever happening.
+    pub fn first_synchronized(&mut self) -> Option<usize> {
+        let cells = self.grid.energy_levels.len();
+        let mut step = 0;
+
+        loop {
+            if step == self.flashes.len() {
+                if self.cycle.is_some() {
+                    return None;
+                }
+                self.advance();
+            }
+
+            if self.flashes[step] == cells {
+                return Some(step + 1);
+            }
+            step += 1;
+        }
     }
 }
 
@@ -110,6 +305,7 @@ fn puzzle_input() -> &'static str {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::XorShift;
 
     fn example_input() -> &'static str {
         include_str!("../inputs/day11_example.txt")
@@ -126,13 +322,157 @@ mod test {
             ";
         let mut grid = OctoGrid::parse(input);
 
-        assert_eq!(vec![1, 5, 6], grid.neighbors(0));
-        assert_eq!(vec![0, 1, 2, 5, 7, 10, 11, 12], grid.neighbors(6));
+        assert_eq!(vec![1, 5, 6], grid.neighbors(0).collect::<Vec<_>>());
+        assert_eq!(
+            vec![0, 1, 2, 5, 7, 10, 11, 12],
+            grid.neighbors(6).collect::<Vec<_>>()
+        );
 
         assert_eq!(9, grid.step());
         assert_eq!(0, grid.step());
     }
 
+    #[test]
+    fn parses_rectangular_grids() {
+        let grid = OctoGrid::parse("123\n456\n\n");
+        assert_eq!(2, grid.rows());
+        assert_eq!(3, grid.cols());
+        assert_eq!(vec![1, 2, 3, 4, 5, 6], grid.energy_levels);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_ragged_grids() {
+        OctoGrid::parse("123\n45\n");
+    }
+
+    #[test]
+    fn finds_neighbors_on_non_square_grids() {
+        // 0  1  2  3  4
+        // 5  6  7  8  9
+        // 10 11 12 13 14
+        let grid = OctoGrid::parse("11111\n11111\n11111\n");
+        assert_eq!(vec![3, 8, 9], grid.neighbors(4).collect::<Vec<_>>());
+        assert_eq!(vec![5, 6, 11], grid.neighbors(10).collect::<Vec<_>>());
+        assert_eq!(
+            vec![2, 3, 4, 7, 9, 12, 13, 14],
+            grid.neighbors(8).collect::<Vec<_>>()
+        );
+
+        let tall = OctoGrid::parse("11\n11\n11\n11\n");
+        assert_eq!(vec![4, 5, 7], tall.neighbors(6).collect::<Vec<_>>());
+        assert_eq!(vec![0, 1, 3, 4, 5], tall.neighbors(2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wraps_around_on_a_torus() {
+        let grid = OctoGrid::parse("11111\n11111\n11111\n").with_topology(Topology::Toroidal);
+        let mut neighbors = grid.neighbors(0).collect::<Vec<_>>();
+        neighbors.sort_unstable();
+        assert_eq!(vec![1, 4, 5, 6, 9, 10, 11, 14], neighbors);
+
+        // Two rows wrap onto each other, so there are only five distinct
+        // neighbors.
+        let thin = OctoGrid::parse("111\n111\n").with_topology(Topology::Toroidal);
+        let mut neighbors = thin.neighbors(0).collect::<Vec<_>>();
+        neighbors.sort_unstable();
+        assert_eq!(vec![1, 2, 3, 4, 5], neighbors);
+    }
+
+    #[test]
+    fn steps_non_square_grids() {
+        let input = "1111111\n9111119\n1111111\n";
+        let mut grid = OctoGrid::parse(input);
+        assert_eq!(2, grid.step());
+        assert_eq!(vec![3, 3, 2, 2, 2, 3, 3], grid.energy_levels[..7].to_vec());
+
+        // Wrapping around, the corners see both flashes.
+        let mut grid = OctoGrid::parse(input).with_topology(Topology::Toroidal);
+        assert_eq!(2, grid.step());
+        assert_eq!(vec![4, 3, 2, 2, 2, 3, 4], grid.energy_levels[..7].to_vec());
+    }
+
+    #[test]
+    fn records_flash_history() {
+        let mut history = FlashHistory::new(OctoGrid::parse(example_input()));
+        assert_eq!(204, history.flashes_after(10));
+        assert_eq!(10, history.flashes().len());
+        assert_eq!(35, history.flashes_at(2));
+
+        assert_eq!(Some(195), history.first_synchronized());
+        // Once they all flash together they stay in step, every ten steps.
+        assert_eq!(100, history.flashes_at(205));
+        assert_eq!(0, history.flashes_at(206));
+    }
+
+    #[test]
+    fn detects_grids_that_never_synchronize() {
+        let mut history = FlashHistory::new(OctoGrid::parse("05"));
+        assert_eq!(None, history.first_synchronized());
+        assert_eq!(
+            Cycle {
+                start: 0,
+                length: 9
+            },
+            history.cycle()
+        );
+        assert_eq!(2_000_000_000, history.flashes_after(9_000_000_000));
+    }
+
+    #[test]
+    fn cycle_answers_match_stepping() {
+        let mut rng = XorShift::new(0x9e37_79b9_7f4a_7c15);
+        let mut next = move || rng.next_u64();
+
+        for _ in 0..50 {
+            let (rows, cols) = (1 + next() as usize % 2, 1 + next() as usize % 3);
+            let input: String = (0..rows)
+                .map(|_| {
+                    (0..cols)
+                        .map(|_| char::from_digit((next() % 10) as u32, 10).unwrap())
+                        .collect::<String>()
+                        + "\n"
+                })
+                .collect();
+
+            let mut history = FlashHistory::new(OctoGrid::parse(&input));
+            let cycle = history.cycle();
+
+            let mut grid = OctoGrid::parse(&input);
+            let mut states = vec![grid.energy_levels.clone()];
+            let mut total = 0;
+            for step in 1..=300 {
+                let flashes = grid.step();
+                total += flashes;
+                assert_eq!(flashes, history.flashes_at(step), "{}", input);
+                assert_eq!(total, history.flashes_after(step), "{}", input);
+                states.push(grid.energy_levels.clone());
+            }
+
+            let repeat = (1..states.len())
+                .find_map(|step| {
+                    let start = states[..step].iter().position(|s| *s == states[step])?;
+                    Some(Cycle {
+                        start,
+                        length: step - start,
+                    })
+                })
+                .unwrap();
+            assert_eq!(repeat, cycle, "{}", input);
+        }
+    }
+
+    #[test]
+    fn worklist_matches_rescan() {
+        let mut grid = bench::generate_grid(40, 60);
+        let mut baseline = bench::generate_grid(40, 60);
+
+        for _ in 0..200 {
+            assert_eq!(bench::rescan_step(&mut baseline), grid.step());
+            assert_eq!(baseline.energy_levels, grid.energy_levels);
+        }
+    }
+
     #[test]
     fn verify_example_input_part1() {
         assert_eq!(1656, solve_part1(example_input()));
@@ -143,3 +483,78 @@ mod test {
         assert_eq!(195, solve_part2(example_input()));
     }
 }
+
+#[cfg(test)]
+mod bench {
+    #[cfg(feature = "bench")]
+    extern crate test;
+
+    use super::*;
+    use crate::XorShift;
+    #[cfg(feature = "bench")]
+    use test::Bencher;
+
+    /// Generates a `rows` by `cols` grid of pseudo-random energy levels.
+    pub fn generate_grid(rows: usize, cols: usize) -> OctoGrid {
+        let mut rng = XorShift::new(0x6a09_e667_f3bc_c908);
+        let mut input = String::new();
+
+        for _ in 0..rows {
+            for _ in 0..cols {
+                input.push(char::from_digit((rng.next_u64() % 10) as u32, 10).unwrap());
+            }
+            input.push('\n');
+        }
+
+        OctoGrid::parse(&input)
+    }
+
+    /// Steps the grid by rescanning all of it until nothing new flashes,
+    /// which is slower than following the flashes but easy to trust.
+    pub fn rescan_step(grid: &mut OctoGrid) -> usize {
+        for level in grid.energy_levels.iter_mut() {
+            *level += 1;
+        }
+
+        let mut changed = true;
+        let mut flashed: Vec<bool> = vec![false; grid.energy_levels.len()];
+
+        while changed {
+            changed = false;
+
+            for (idx, flash) in flashed.iter_mut().enumerate() {
+                if grid.energy_levels[idx] > 9 && !*flash {
+                    changed = true;
+                    *flash = true;
+
+                    let neighbors: Vec<usize> = grid.neighbors(idx).collect();
+                    for neighbor in neighbors {
+                        grid.energy_levels[neighbor] += 1;
+                    }
+                }
+            }
+        }
+
+        for level in grid.energy_levels.iter_mut() {
+            if *level > 9 {
+                *level = 0;
+            }
+        }
+
+        flashed.iter().filter(|&b| *b).count()
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn step_worklist(b: &mut Bencher) {
+        let mut grid = generate_grid(1000, 1000);
+        b.iter(|| grid.step());
+    }
+
+    #[cfg(feature = "bench")]
+    #[bench]
+    fn step_rescan(b: &mut Bencher) {
+        let mut grid = generate_grid(1000, 1000);
+        b.iter(|| rescan_step(&mut grid));
+    }
+}
@@ -1,4 +1,4 @@
-#![feature(hash_drain_filter)]
+#![cfg_attr(all(test, feature = "bench"), feature(test))]
 pub mod day01;
 pub mod day02;
 pub mod day03;
@@ -18,3 +18,24 @@ pub mod day16;
 pub mod day17;
 pub mod day18;
 pub mod runner;
+
+/// A small seeded pseudo-random number generator (xorshift64), so tests
+/// can generate the same inputs every run.
+#[cfg(test)]
+pub(crate) struct XorShift(u64);
+
+#[cfg(test)]
+impl XorShift {
+    /// The seed must not be 0, which xorshift would never leave.
+    pub fn new(seed: u64) -> XorShift {
+        assert_ne!(0, seed);
+        XorShift(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}